@@ -0,0 +1,4 @@
+fn main() {
+    // packet ids are resolved from the reports by `packet_id` at compile time
+    println!("cargo:rerun-if-changed=reports");
+}
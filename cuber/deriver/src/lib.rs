@@ -4,120 +4,691 @@ use proc_macro::TokenStream as RawToken;
 use proc_macro2::TokenStream;
 use syn::{parse_macro_input, Fields};
 
-use quote::quote;
+use quote::{format_ident, quote};
 
-#[proc_macro_derive(Encodable)]
-pub fn derive_encodable(input: RawToken) -> RawToken {
-    let st = parse_macro_input!(input as syn::DeriveInput);
+// `#[cuber(..)]` on a field
+#[derive(Default)]
+struct FieldAttrs {
+    // `if = "expr"`: only on the wire when the expression holds. earlier fields are in scope as references.
+    // the field is an `Option`, or the type itself with `default`.
+    condition: Option<syn::Expr>,
+    // `skip`: never on the wire, decoded as `Default::default()`
+    skip: bool,
+    // `default`: an absent conditional field is `Default::default()` instead of `None`
+    default: bool,
+    // `with = "module"`: `module::encode(&value, writer)`, `module::encoded_len(&value)`
+    // and `module::decode(reader)` instead of the traits
+    with: Option<syn::Path>,
+    // `len = VarIntLength`: a `Vec` prefixed with the given `ArrayLength`
+    len: Option<syn::Type>,
+    // `max_len = N`: decoded with `BoundedDecodable` instead of `Decodable`.
+    // `#[max_len = N]` on its own is the same.
+    max_len: Option<syn::Expr>,
+    // `versions = V1_20_1..`: only exists in protocol versions in the range.
+    // bare version names are resolved to `ProtocolVersion` constants.
+    versions: Option<syn::ExprRange>,
+    // the options as written, for errors to point at
+    keys: Vec<syn::Ident>,
+}
 
-    let ident = st.ident.clone();
+impl FieldAttrs {
+    fn of(field: &syn::Field) -> syn::Result<Self> {
+        let mut attrs = Self::default();
+        for attr in field
+            .attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("max_len"))
+        {
+            attrs.key(attr.path().require_ident()?)?;
+            attrs.max_len = Some(attr.meta.require_name_value()?.value.clone());
+        }
+        for attr in field
+            .attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("cuber"))
+        {
+            attr.parse_nested_meta(|meta| {
+                attrs.key(meta.path.require_ident()?)?;
+                if meta.path.is_ident("if") {
+                    let expr: syn::LitStr = meta.value()?.parse()?;
+                    attrs.condition = Some(expr.parse()?);
+                } else if meta.path.is_ident("skip") {
+                    attrs.skip = true;
+                } else if meta.path.is_ident("default") {
+                    attrs.default = true;
+                } else if meta.path.is_ident("with") {
+                    let module: syn::LitStr = meta.value()?.parse()?;
+                    attrs.with = Some(module.parse()?);
+                } else if meta.path.is_ident("len") {
+                    attrs.len = Some(meta.value()?.parse()?);
+                } else if meta.path.is_ident("max_len") {
+                    attrs.max_len = Some(meta.value()?.parse()?);
+                } else if meta.path.is_ident("versions") {
+                    attrs.versions = Some(meta.value()?.parse()?);
+                } else {
+                    return Err(
+                        meta.error("expected if, skip, default, with, len, max_len or versions")
+                    );
+                }
+                Ok(())
+            })?;
+        }
+
+        if attrs.default && attrs.condition.is_none() {
+            return Err(attrs.error("default", "#[cuber(default)] needs #[cuber(if = ..)]"));
+        }
+        // each of these decides how the field is read, so at most one applies
+        let readers = attrs
+            .keys
+            .iter()
+            .filter(|key| *key == "with" || *key == "len" || *key == "max_len")
+            .collect::<Vec<_>>();
+        if let [first, second, ..] = readers[..] {
+            return Err(syn::Error::new(
+                second.span(),
+                format!("#[cuber({}, {})] can not be used together", first, second),
+            ));
+        }
+        if attrs.skip && attrs.keys.len() > 1 {
+            return Err(attrs.error(
+                "skip",
+                "#[cuber(skip)] can not be used with other #[cuber(..)] options",
+            ));
+        }
+        Ok(attrs)
+    }
+
+    // remembers an option, which may be given only once
+    fn key(&mut self, key: &syn::Ident) -> syn::Result<()> {
+        if self.keys.contains(key) {
+            return Err(syn::Error::new(key.span(), format!("duplicate `{}`", key)));
+        }
+        self.keys.push(key.clone());
+        Ok(())
+    }
+
+    // an error pointing at an option that was given
+    fn error(&self, key: &str, message: &str) -> syn::Error {
+        let span = self
+            .keys
+            .iter()
+            .find(|k| *k == key)
+            .map_or_else(proc_macro2::Span::call_site, |k| k.span());
+        syn::Error::new(span, message)
+    }
+
+    // `(V1_20_1..).contains(&ProtocolVersion::current())`
+    fn versions_condition(&self) -> Option<TokenStream> {
+        let range = self.versions.as_ref()?;
+        let bound = |bound: &Option<Box<syn::Expr>>| match bound.as_deref() {
+            Some(syn::Expr::Path(path)) if path.path.get_ident().is_some() => {
+                quote! { ProtocolVersion::#path }
+            }
+            Some(expr) => quote! { #expr },
+            None => quote! {},
+        };
+        let start = bound(&range.start);
+        let end = bound(&range.end);
+        let limits = range.limits;
 
-    let write_code = match st.data {
-        syn::Data::Struct(ref str) => match str.fields {
-            Fields::Unit => quote! {},
-            Fields::Named(ref names) => {
-                let encode_part = names
-                    .named
-                    .iter()
-                    .map(|name| {
-                        let name = name
-                            .ident
-                            .clone()
-                            .expect("(TODO) unnamed structure is not allowed");
-                        quote! {
-                            __written += self.#name.encode(writer);
-                        }
-                    })
-                    .fold(quote! {}, |acc, mem| quote! { #acc #mem });
-
-                quote! {
-                    let mut __written = 0;
-                    #encode_part
-                    __written
+        Some(quote! { (#start #limits #end).contains(&ProtocolVersion::current()) })
+    }
+}
+
+// `Array<len, _>`, by its full path so that the derive works without importing it
+fn array(len: &syn::Type) -> TokenStream {
+    quote! { crate::protocol::primitive::array::Array::<#len, _> }
+}
+
+// `encode` and `encoded_len` are generated from the same walk over the fields, adding up `__written`.
+#[derive(Clone, Copy)]
+enum Emit {
+    Encode,
+    Len,
+}
+
+// encodes a field bound (by reference) to `binding`, skipped outside of `#[cuber(versions = ..)]`
+fn encode_field(
+    binding: &syn::Ident,
+    field: &syn::Field,
+    label: &TokenStream,
+    emit: Emit,
+) -> syn::Result<TokenStream> {
+    let attrs = FieldAttrs::of(field)?;
+    if attrs.skip {
+        return Ok(quote! {});
+    }
+
+    let write = |value: TokenStream| match emit {
+        Emit::Encode => {
+            let encode = match (&attrs.with, &attrs.len) {
+                (Some(with), _) => quote! { #with::encode(#value, writer) },
+                (_, Some(len)) => {
+                    let array = array(len);
+                    quote! { #array::encode_slice(#value, writer) }
                 }
+                _ => quote! { #value.encode(writer) },
+            };
+            quote! {
+                __written += anyhow::Context::with_context(#encode, || format!("Failed to encode {}", stringify!(#label)))?;
             }
-            Fields::Unnamed(ref _unnamed) => {
-                todo!()
+        }
+        Emit::Len => {
+            let len = match (&attrs.with, &attrs.len) {
+                (Some(with), _) => quote! { #with::encoded_len(#value) },
+                (_, Some(len)) => {
+                    let array = array(len);
+                    quote! { #array::encoded_len_slice(#value) }
+                }
+                _ => quote! { #value.encoded_len() },
+            };
+            quote! { __written += #len; }
+        }
+    };
+
+    let encode = match &attrs.condition {
+        None => write(quote! { #binding }),
+        Some(condition) if attrs.default => {
+            let write = write(quote! { #binding });
+            quote! { if #condition { #write } }
+        }
+        Some(condition) => {
+            let write = write(quote! { __value });
+            let missing = match emit {
+                Emit::Encode => quote! {
+                    anyhow::bail!("{} is missing while `{}` holds", stringify!(#label), stringify!(#condition))
+                },
+                Emit::Len => quote! {},
+            };
+            quote! {
+                if #condition {
+                    match #binding {
+                        Some(__value) => { #write }
+                        None => { #missing }
+                    }
+                }
             }
+        }
+    };
+
+    Ok(match attrs.versions_condition() {
+        Some(condition) => quote! { if #condition { #encode } },
+        None => encode,
+    })
+}
+
+// `prefix` qualifies the field names in error messages, like `Variant.`
+fn encode_fields(fields: &Fields, prefix: TokenStream, emit: Emit) -> syn::Result<TokenStream> {
+    fields
+        .iter()
+        .zip(field_bindings(fields))
+        .zip(field_members(fields))
+        .map(|((field, binding), member)| {
+            encode_field(&binding, field, &quote! { #prefix #member }, emit)
+        })
+        .collect()
+}
+
+// `let binding = decode(reader)`, which is `Default::default()` outside of `#[cuber(versions = ..)]`.
+// `previous` are the fields decoded so far, which `#[cuber(if = ..)]` may refer to.
+// `decode` reads from any `reader`, `decode_bytes` takes from the front of `buf` so that byte fields can slice it.
+#[derive(Clone, Copy)]
+enum Source {
+    Reader,
+    Bytes,
+}
+
+fn decode_field(
+    binding: &syn::Ident,
+    field: &syn::Field,
+    label: &TokenStream,
+    previous: &[syn::Ident],
+    source: Source,
+) -> syn::Result<TokenStream> {
+    let attrs = FieldAttrs::of(field)?;
+    if attrs.skip {
+        return Ok(quote! { let #binding = Default::default(); });
+    }
+
+    let mtype = field.ty.clone();
+
+    let read = match (&attrs.with, &attrs.len, &attrs.max_len) {
+        (Some(with), _, _) => quote! { #with::decode(reader) },
+        (_, Some(len), _) => {
+            let array = array(len);
+            quote! { #array::decode_vec(reader) }
+        }
+        (_, _, Some(max_len)) => quote! { BoundedDecodable::decode_bounded(reader, #max_len) },
+        _ => quote! { Decodable::decode(reader) },
+    };
+    let decode = match source {
+        Source::Reader => read,
+        Source::Bytes => match (&attrs.with, &attrs.len, &attrs.max_len) {
+            // `with` modules only know readers
+            (Some(_), _, _) => quote! { crate::protocol::decode_from_slice(buf, |reader| #read) },
+            (_, Some(len), _) => {
+                let array = array(len);
+                quote! { #array::decode_vec_bytes(buf) }
+            }
+            (_, _, Some(max_len)) => {
+                quote! { BoundedDecodable::decode_bytes_bounded(buf, #max_len) }
+            }
+            _ => quote! { Decodable::decode_bytes(buf) },
+        },
+    };
+    let decode = quote! {
+        anyhow::Context::with_context(#decode, || format!("Failed to decode {}: {}", stringify!(#label), stringify!(#mtype)))?
+    };
+
+    let decode = match &attrs.condition {
+        None => decode,
+        Some(condition) => {
+            let (present, absent) = if attrs.default {
+                (decode, quote! { Default::default() })
+            } else {
+                (quote! { Some(#decode) }, quote! { None })
+            };
+            quote! {
+                if {
+                    #(#[allow(unused_variables)] let #previous = &#previous;)*
+                    #condition
+                } {
+                    #present
+                } else {
+                    #absent
+                }
+            }
+        }
+    };
+
+    Ok(match attrs.versions_condition() {
+        Some(condition) => quote! {
+            let #binding = if #condition { #decode } else { Default::default() };
         },
-        _ => {
-            panic!("encodable derive only support struct");
+        None => quote! { let #binding = #decode; },
+    })
+}
+
+fn decode_fields(fields: &Fields, prefix: TokenStream, source: Source) -> syn::Result<TokenStream> {
+    let bindings = field_bindings(fields);
+    fields
+        .iter()
+        .zip(field_members(fields))
+        .enumerate()
+        .map(|(i, (field, member))| {
+            decode_field(
+                &bindings[i],
+                field,
+                &quote! { #prefix #member },
+                &bindings[..i],
+                source,
+            )
+        })
+        .collect()
+}
+
+// names the fields are bound to in patterns and constructors. tuple fields become `__0`, `__1`, ...
+fn field_bindings(fields: &Fields) -> Vec<syn::Ident> {
+    fields
+        .iter()
+        .enumerate()
+        .map(|(i, field)| match &field.ident {
+            Some(ident) => ident.clone(),
+            None => format_ident!("__{}", i),
+        })
+        .collect()
+}
+
+// `self.name` or `self.0`
+fn field_members(fields: &Fields) -> Vec<syn::Member> {
+    fields
+        .iter()
+        .enumerate()
+        .map(|(i, field)| match &field.ident {
+            Some(ident) => syn::Member::Named(ident.clone()),
+            None => syn::Member::Unnamed(i.into()),
+        })
+        .collect()
+}
+
+// `{ a, b }`, `(__0, __1)` or nothing, usable both as a pattern and as a constructor
+fn fields_pattern(fields: &Fields) -> TokenStream {
+    let bindings = field_bindings(fields);
+    match fields {
+        Fields::Named(_) => quote! { { #(#bindings),* } },
+        Fields::Unnamed(_) => quote! { ( #(#bindings),* ) },
+        Fields::Unit => quote! {},
+    }
+}
+
+// every type parameter has to be encodable (decodable) itself, as the std derives assume
+fn bounded_generics(generics: &syn::Generics, bound: TokenStream) -> syn::Generics {
+    let mut generics = generics.clone();
+    let params = generics
+        .type_params()
+        .map(|param| param.ident.clone())
+        .collect::<Vec<_>>();
+    let where_clause = generics.make_where_clause();
+    for param in params {
+        where_clause
+            .predicates
+            .push(syn::parse_quote! { #param: #bound });
+    }
+    generics
+}
+
+// `#[repr_kind(varint)]` on an enum: how the discriminant is written on the wire.
+enum ReprKind {
+    VarInt,
+    U8,
+    I8,
+    String,
+}
+
+impl ReprKind {
+    fn of(attrs: &[syn::Attribute]) -> syn::Result<Self> {
+        let Some(attr) = attrs.iter().find(|attr| attr.path().is_ident("repr_kind")) else {
+            return Err(syn::Error::new(
+                proc_macro2::Span::call_site(),
+                "enum needs #[repr_kind(varint|u8|i8|string)]",
+            ));
+        };
+        let kind: syn::Ident = attr.parse_args().map_err(|e| {
+            syn::Error::new(
+                e.span(),
+                "repr_kind must be one of varint, u8, i8 or string",
+            )
+        })?;
+
+        match kind.to_string().as_str() {
+            "varint" => Ok(Self::VarInt),
+            "u8" => Ok(Self::U8),
+            "i8" => Ok(Self::I8),
+            "string" => Ok(Self::String),
+            _ => Err(syn::Error::new(
+                kind.span(),
+                format!("unknown repr_kind: {}", kind),
+            )),
+        }
+    }
+
+    // the type on the wire
+    fn wire_type(&self) -> TokenStream {
+        match self {
+            Self::VarInt => quote! { VarInt },
+            Self::U8 => quote! { u8 },
+            Self::I8 => quote! { i8 },
+            Self::String => quote! { String },
+        }
+    }
+
+    // the type `#[value = ..]` is written in
+    fn value_type(&self) -> TokenStream {
+        match self {
+            Self::VarInt => quote! { i32 },
+            Self::U8 => quote! { u8 },
+            Self::I8 => quote! { i8 },
+            Self::String => quote! { &'static str },
         }
+    }
+
+    // a value as written in `#[value = ..]` to the type on the wire
+    fn wire(&self, value: TokenStream) -> TokenStream {
+        match self {
+            Self::VarInt => quote! { VarInt::from(#value) },
+            Self::String => quote! { String::from(#value) },
+            Self::U8 | Self::I8 => value,
+        }
+    }
+
+    // a decoded discriminant to something matchable against `#[value = ..]`
+    fn wire_value(&self, raw: TokenStream) -> TokenStream {
+        match self {
+            Self::VarInt => quote! { i32::from(#raw) },
+            Self::String => quote! { #raw.as_str() },
+            Self::U8 | Self::I8 => raw,
+        }
+    }
+}
+
+// `#[value = N]` on a variant. negative values are not literals to rustc, so they are written `#[value(-1)]`.
+fn variant_value(variant: &syn::Variant) -> syn::Result<Option<syn::Expr>> {
+    let Some(attr) = variant
+        .attrs
+        .iter()
+        .find(|attr| attr.path().is_ident("value"))
+    else {
+        return Ok(None);
     };
+    match &attr.meta {
+        syn::Meta::NameValue(nv) => Ok(Some(nv.value.clone())),
+        syn::Meta::List(list) => list.parse_args().map(Some).map_err(|e| {
+            syn::Error::new(e.span(), "value must be an expression like #[value(-1)]")
+        }),
+        syn::Meta::Path(path) => Err(syn::Error::new_spanned(path, "expected #[value = ..]")),
+    }
+}
+
+// a variant without `#[value = ..]` holding a single field is the catch-all for unknown discriminants,
+// like `Other(u8)`. the field keeps the raw discriminant.
+fn is_catch_all(variant: &syn::Variant) -> bool {
+    matches!(&variant.fields, Fields::Unnamed(fields) if fields.unnamed.len() == 1)
+}
+
+// the variants with their `#[value = ..]`, and the catch-all if there is one
+type Variants<'a> = (Vec<(&'a syn::Variant, syn::Expr)>, Option<&'a syn::Variant>);
+
+fn checked_variants(data: &syn::DataEnum) -> syn::Result<Variants<'_>> {
+    let mut valued = vec![];
+    let mut catch_all = None;
+    for variant in &data.variants {
+        match variant_value(variant)? {
+            Some(value) => valued.push((variant, value)),
+            None if is_catch_all(variant) && catch_all.is_none() => catch_all = Some(variant),
+            None => {
+                return Err(syn::Error::new(
+                    variant.ident.span(),
+                    format!("variant {} needs #[value = ..]", variant.ident),
+                ))
+            }
+        }
+    }
+    Ok((valued, catch_all))
+}
+
+fn encode_enum(
+    attrs: &[syn::Attribute],
+    data: &syn::DataEnum,
+    emit: Emit,
+) -> syn::Result<TokenStream> {
+    let kind = ReprKind::of(attrs)?;
+    let wire_type = kind.wire_type();
+    let value_type = kind.value_type();
+    let (valued, catch_all) = checked_variants(data)?;
+
+    let arms = valued
+        .iter()
+        .map(|(variant, value)| {
+            let vname = &variant.ident;
+            let pattern = fields_pattern(&variant.fields);
+            let wire = kind.wire(quote! { __value });
+            let encode_fields = encode_fields(&variant.fields, quote! { #vname. }, emit)?;
+            let encode_value = match emit {
+                Emit::Encode => quote! {
+                    __written += anyhow::Context::with_context(#wire.encode(writer), || format!("Failed to encode discriminant of {}", stringify!(#vname)))?;
+                },
+                Emit::Len => quote! { __written += #wire.encoded_len(); },
+            };
+
+            Ok(quote! {
+                Self::#vname #pattern => {
+                    let __value: #value_type = #value;
+                    #encode_value
+                    #encode_fields
+                }
+            })
+        })
+        .collect::<syn::Result<TokenStream>>()?;
+
+    let catch_all = catch_all.map(|variant| {
+        let vname = &variant.ident;
+        let encode_value = match emit {
+            Emit::Encode => quote! {
+                __written += anyhow::Context::with_context(__raw.encode(writer), || format!("Failed to encode discriminant of {}", stringify!(#vname)))?;
+            },
+            Emit::Len => quote! { __written += __raw.encoded_len(); },
+        };
+        quote! {
+            Self::#vname(__raw) => {
+                let __raw: #wire_type = Clone::clone(__raw).into();
+                #encode_value
+            }
+        }
+    });
+
+    Ok(quote! {
+        let mut __written = 0;
+        match self {
+            #arms
+            #catch_all
+        }
+    })
+}
+
+fn decode_enum(
+    ident: &syn::Ident,
+    attrs: &[syn::Attribute],
+    data: &syn::DataEnum,
+    source: Source,
+) -> syn::Result<TokenStream> {
+    let kind = ReprKind::of(attrs)?;
+    let wire_type = kind.wire_type();
+    let (valued, catch_all) = checked_variants(data)?;
+
+    let arms = valued
+        .iter()
+        .map(|(variant, value)| {
+            let vname = &variant.ident;
+            let construct = fields_pattern(&variant.fields);
+            let decode_fields = decode_fields(&variant.fields, quote! { #vname. }, source)?;
+
+            Ok(quote! {
+                #value => {
+                    #decode_fields
+                    Ok(Self::#vname #construct)
+                }
+            })
+        })
+        .collect::<syn::Result<TokenStream>>()?;
+
+    let fallback = match catch_all {
+        Some(variant) => {
+            let vname = &variant.ident;
+            quote! { _ => Ok(Self::#vname(__raw.into())), }
+        }
+        None => quote! { __value => anyhow::bail!("Invalid {}: {}", stringify!(#ident), __value), },
+    };
+    let value = kind.wire_value(quote! { __raw });
+    let decode = match source {
+        Source::Reader => quote! { <#wire_type as Decodable>::decode(reader) },
+        Source::Bytes => quote! { <#wire_type as Decodable>::decode_bytes(buf) },
+    };
+
+    Ok(quote! {
+        let __raw = anyhow::Context::with_context(#decode, || format!("Failed to decode discriminant of {}", stringify!(#ident)))?;
+        match #value {
+            #arms
+            #fallback
+        }
+    })
+}
+
+#[proc_macro_derive(Encodable, attributes(repr_kind, value, cuber, max_len))]
+pub fn derive_encodable(input: RawToken) -> RawToken {
+    let st = parse_macro_input!(input as syn::DeriveInput);
+
+    let ident = st.ident.clone();
+
+    let walk = |emit: Emit| match st.data {
+        syn::Data::Struct(ref str) => {
+            let pattern = fields_pattern(&str.fields);
+            let encode_part = encode_fields(&str.fields, quote! {}, emit)?;
+
+            Ok(quote! {
+                #[allow(unused_variables)]
+                let Self #pattern = self;
+                let mut __written = 0;
+                #encode_part
+            })
+        }
+        syn::Data::Enum(ref data) => encode_enum(&st.attrs, data, emit),
+        syn::Data::Union(_) => {
+            panic!("encodable derive does not support union");
+        }
+    };
+    let (write_code, len_code) = match (walk(Emit::Encode), walk(Emit::Len)) {
+        (Ok(write_code), Ok(len_code)) => (write_code, len_code),
+        (Err(e), _) | (_, Err(e)) => return e.to_compile_error().into(),
+    };
+
+    let generics = bounded_generics(&st.generics, quote! { Encodable });
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
 
     quote! {
-        impl Encodable for #ident {
-            fn encode<T: std::io::Write>(&self, writer: &mut T) -> usize {
+        impl #impl_generics Encodable for #ident #ty_generics #where_clause {
+            fn encode<__W: std::io::Write>(&self, writer: &mut __W) -> anyhow::Result<usize> {
                 #write_code
+                Ok(__written)
+            }
+            fn encoded_len(&self) -> usize {
+                #len_code
+                __written
             }
         }
     }
     .into()
 }
 
-#[proc_macro_derive(Decodable)]
+#[proc_macro_derive(Decodable, attributes(repr_kind, value, cuber, max_len))]
 pub fn derive_decoable(input: RawToken) -> RawToken {
     let st = parse_macro_input!(input as syn::DeriveInput);
 
     let ident = st.ident.clone();
 
-    let read_code = match st.data {
-        syn::Data::Struct(ref str) => match str.fields {
-            Fields::Unit => quote! {},
-            Fields::Named(ref names) => {
-                let decode_part = names
-                    .named
-                    .iter()
-                    .map(|name| {
-                        let mname = name
-                            .ident
-                            .clone()
-                            .expect("(TODO) unnamed structure is not allowed");
-                        let mtype = name.ty.clone();
-
-                        quote! {
-                            let #mname = Decodable::decode(reader).with_context(|| format!("Failed to decode {}: {}", stringify!(#mname), stringify!(#mtype)))?;
-                        }
-                    })
-                    .fold(TokenStream::new(), |acc, mem| quote! { #acc #mem });
-
-                let construct_part = names
-                    .named
-                    .iter()
-                    .map(|name| {
-                        let name = name
-                            .ident
-                            .clone()
-                            .expect("(TODO) unnamed structure is not allowed");
-                        quote! {
-                            #name,
-                        }
-                    })
-                    .fold(quote! {}, |acc, mem| quote! { #acc #mem });
-
-                quote! {
-                    #decode_part
-                    Ok(Self {
-                        #construct_part
-                    })
-                }
-            }
-            Fields::Unnamed(ref _unnamed) => {
-                todo!()
-            }
-        },
-        _ => {
-            panic!("encodable derive only support struct");
+    let walk = |source: Source| match st.data {
+        syn::Data::Struct(ref str) => {
+            let decode_part = decode_fields(&str.fields, quote! {}, source)?;
+
+            let construct_part = fields_pattern(&str.fields);
+
+            Ok(quote! {
+                #decode_part
+                Ok(Self #construct_part)
+            })
         }
+        syn::Data::Enum(ref data) => decode_enum(&ident, &st.attrs, data, source),
+        syn::Data::Union(_) => {
+            panic!("decodable derive does not support union");
+        }
+    };
+    let (read_code, bytes_code) = match (walk(Source::Reader), walk(Source::Bytes)) {
+        (Ok(read_code), Ok(bytes_code)) => (read_code, bytes_code),
+        (Err(e), _) | (_, Err(e)) => return e.to_compile_error().into(),
     };
 
     // dbg!(read_code.to_string());
 
+    let generics = bounded_generics(&st.generics, quote! { Decodable });
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
     quote! {
-        impl Decodable for #ident {
-            fn decode<T: std::io::Read>(reader: &mut T) -> anyhow::Result<Self> {
+        impl #impl_generics Decodable for #ident #ty_generics #where_clause {
+            fn decode<__R: std::io::Read>(reader: &mut __R) -> anyhow::Result<Self> {
                 #read_code
             }
+            fn decode_bytes(buf: &mut bytes::Bytes) -> anyhow::Result<Self> {
+                #bytes_code
+            }
         }
     }
     .into()
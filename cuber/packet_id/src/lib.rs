@@ -1,34 +1,376 @@
 use proc_macro::TokenStream as RawToken;
+use proc_macro2::TokenStream;
 use quote::quote;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
 use syn::{
     parse::{Parse, ParseStream},
-    parse_macro_input,
+    parse_macro_input, Token,
 };
 
+// `0x14, V1_20_2 = 0x16, ...`
+// the first id is for the oldest supported version, and each `Version = id` takes over from that version on.
+// `None` marks versions where the packet does not exist.
+struct PacketIds {
+    first: syn::Expr,
+    changes: Vec<(syn::Ident, syn::Expr)>,
+}
+
+impl Parse for PacketIds {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let first = syn::Expr::parse(input)?;
+
+        let mut changes = vec![];
+        while !input.is_empty() {
+            let _comma = <Token![,]>::parse(input)?;
+            if input.is_empty() {
+                break;
+            }
+            let version = syn::Ident::parse(input)?;
+            let _eq = <Token![=]>::parse(input)?;
+            let id = syn::Expr::parse(input)?;
+            changes.push((version, id));
+        }
+
+        Ok(Self { first, changes })
+    }
+}
+
+// ids are either written out, or looked up by resource name (`"minecraft:login"`) in the reports.
+enum PacketIdSource {
+    Ids(PacketIds),
+    Name(PacketName),
+}
+
+// `"minecraft:finish_configuration", since = V1_20_2`
+// a name must be in every report, unless `since` (the first version that has it)
+// or `removed` (the first version that does not) limit the versions it exists in.
+struct PacketName {
+    name: syn::LitStr,
+    since: Option<syn::Ident>,
+    removed: Option<syn::Ident>,
+}
+
+impl Parse for PacketName {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let name = input.parse::<syn::LitStr>()?;
+        let mut since = None;
+        let mut removed = None;
+        while !input.is_empty() {
+            let _comma = <Token![,]>::parse(input)?;
+            if input.is_empty() {
+                break;
+            }
+            let key = syn::Ident::parse(input)?;
+            let _eq = <Token![=]>::parse(input)?;
+            let version = syn::Ident::parse(input)?;
+            let slot = match key.to_string().as_str() {
+                "since" => &mut since,
+                "removed" => &mut removed,
+                _ => {
+                    return Err(syn::Error::new(
+                        key.span(),
+                        "expected `since = <version>` or `removed = <version>`",
+                    ))
+                }
+            };
+            if slot.replace(version).is_some() {
+                return Err(syn::Error::new(key.span(), format!("duplicate `{}`", key)));
+            }
+        }
+
+        Ok(Self {
+            name,
+            since,
+            removed,
+        })
+    }
+}
+
+impl Parse for PacketIdSource {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        if input.peek(syn::LitStr) {
+            return Ok(Self::Name(PacketName::parse(input)?));
+        }
+        Ok(Self::Ids(PacketIds::parse(input)?))
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Direction {
+    ClientBound,
+    ServerBound,
+}
+
+impl Direction {
+    fn key(self) -> &'static str {
+        match self {
+            Direction::ClientBound => "clientbound",
+            Direction::ServerBound => "serverbound",
+        }
+    }
+}
+
+// `reports/<version>/packets.json` of the crate using the macro, in the layout of the vanilla data generator.
+// see `reports/README.md` for where each file comes from.
+struct Report {
+    version: Vec<u32>,
+    // `V1_20_2`, kept as a string so that reports can be shared between macro invocations
+    ident: String,
+    packets: serde_json::Value,
+}
+
+fn reports_dir() -> PathBuf {
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap_or_else(|_| ".".to_string());
+    PathBuf::from(manifest_dir).join("reports")
+}
+
+// the reports sorted by version, or why they could not be read
+type Reports = Result<Vec<Report>, String>;
+
+// every packet name resolves against all reports, so they are read once per crate.
+// the key matters to an editor, whose proc macro server stays alive across crates.
+fn load_reports() -> Arc<Reports> {
+    static REPORTS: Mutex<BTreeMap<PathBuf, Arc<Reports>>> = Mutex::new(BTreeMap::new());
+
+    let dir = reports_dir();
+    let mut reports = REPORTS.lock().unwrap_or_else(PoisonError::into_inner);
+    reports
+        .entry(dir)
+        .or_insert_with_key(|dir| Arc::new(read_reports(dir)))
+        .clone()
+}
+
+fn read_reports(dir: &Path) -> Reports {
+    let entries = std::fs::read_dir(dir)
+        .map_err(|e| format!("failed to read packet reports at {}: {}", dir.display(), e))?;
+
+    let mut reports = vec![];
+    for entry in entries {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        let Ok(version) = name
+            .split('.')
+            .map(str::parse)
+            .collect::<Result<Vec<u32>, _>>()
+        else {
+            continue;
+        };
+        // 1.20.2 -> ProtocolVersion::V1_20_2
+        let ident = format!("V{}", name.replace('.', "_"));
+
+        let file = path.join("packets.json");
+        let json = std::fs::read_to_string(&file)
+            .map_err(|e| format!("failed to read {}: {}", file.display(), e))?;
+        let packets = serde_json::from_str(&json)
+            .map_err(|e| format!("failed to parse {}: {}", file.display(), e))?;
+
+        reports.push(Report {
+            version,
+            ident,
+            packets,
+        });
+    }
+    reports.sort_by(|a, b| a.version.cmp(&b.version));
+
+    Ok(reports)
+}
+
+fn state_key(state: &syn::Ident) -> String {
+    match state.to_string().as_str() {
+        "Handshaking" => "handshake".to_string(),
+        state => state.to_lowercase(),
+    }
+}
+
+impl PacketIdSource {
+    fn resolve(self, state: &syn::Ident, direction: Direction) -> syn::Result<PacketIds> {
+        let PacketName {
+            name,
+            since,
+            removed,
+        } = match self {
+            PacketIdSource::Ids(ids) => return Ok(ids),
+            PacketIdSource::Name(name) => name,
+        };
+        let reports = load_reports();
+        let reports = reports
+            .as_ref()
+            .as_ref()
+            .map_err(|e| syn::Error::new(name.span(), e))?;
+
+        // index of the first report at or after `version`
+        let position = |version: &Option<syn::Ident>, default: usize| -> syn::Result<usize> {
+            let Some(version) = version else {
+                return Ok(default);
+            };
+            reports
+                .iter()
+                .position(|report| *version == report.ident)
+                .ok_or_else(|| {
+                    syn::Error::new(
+                        version.span(),
+                        format!(
+                            "there is no report for `{}` under {}",
+                            version,
+                            reports_dir().display()
+                        ),
+                    )
+                })
+        };
+        let since = position(&since, 0)?;
+        let removed = position(&removed, reports.len())?;
+
+        let ids = reports
+            .iter()
+            .map(|report| {
+                report.packets[state_key(state)][direction.key()][name.value()]["protocol_id"]
+                    .as_i64()
+                    .map(|id| id as i32)
+            })
+            .collect::<Vec<_>>();
+
+        for (i, (report, id)) in reports.iter().zip(&ids).enumerate() {
+            let expected = (since..removed).contains(&i);
+            if expected == id.is_some() {
+                continue;
+            }
+            let message = if expected {
+                format!(
+                    "{} packet `{}` is not found in the {} state of the {} report; \
+                     use `since` or `removed` if it only exists in some versions",
+                    direction.key(),
+                    name.value(),
+                    state_key(state),
+                    report.ident,
+                )
+            } else {
+                format!(
+                    "{} packet `{}` exists in the {} state of the {} report, \
+                     which is outside of its `since`/`removed` range",
+                    direction.key(),
+                    name.value(),
+                    state_key(state),
+                    report.ident,
+                )
+            };
+            return Err(syn::Error::new(name.span(), message));
+        }
+
+        let id_expr = |id: Option<i32>| -> syn::Expr {
+            match id {
+                Some(id) => syn::parse_quote! { #id },
+                None => syn::parse_quote! { None },
+            }
+        };
+        let first = id_expr(ids[0]);
+        let mut changes = vec![];
+        // only versions where the id actually changes need a branch
+        for (i, report) in reports.iter().enumerate().skip(1) {
+            if ids[i] != ids[i - 1] {
+                let ident = syn::Ident::new(&report.ident, proc_macro2::Span::call_site());
+                changes.push((ident, id_expr(ids[i])));
+            }
+        }
+
+        Ok(PacketIds { first, changes })
+    }
+}
+
+fn is_none(id: &syn::Expr) -> bool {
+    matches!(id, syn::Expr::Path(path) if path.path.is_ident("None"))
+}
+
+fn packet_id_value(id: &syn::Expr) -> TokenStream {
+    if is_none(id) {
+        quote! { None }
+    } else {
+        quote! { Some(#id) }
+    }
+}
+
+impl PacketIds {
+    fn packet_id_fn(&self) -> TokenStream {
+        let first = packet_id_value(&self.first);
+        let changes = self
+            .changes
+            .iter()
+            .rev()
+            .map(|(version, id)| {
+                let id = packet_id_value(id);
+                quote! {
+                    if version >= ProtocolVersion::#version {
+                        return #id;
+                    }
+                }
+            })
+            .fold(TokenStream::new(), |acc, change| quote! { #acc #change });
+
+        quote! {
+            fn packet_id(version: ProtocolVersion) -> Option<i32> {
+                #changes
+                #first
+            }
+        }
+    }
+}
+
+// `0x14, V1_20_2 = 0x16` or `State::Play, "minecraft:move_player_pos"`
 struct SBPacketIdArgs {
-    id: syn::Expr,
+    state: Option<syn::Path>,
+    ids: PacketIdSource,
 }
 
 impl Parse for SBPacketIdArgs {
     fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
-        let id = syn::Expr::parse(input)?;
+        let state = if input.peek(syn::Ident) && input.peek2(Token![::]) {
+            let state = syn::Path::parse(input)?;
+            let _comma = <Token![,]>::parse(input)?;
+            Some(state)
+        } else {
+            None
+        };
+        let ids = PacketIdSource::parse(input)?;
 
-        Ok(Self { id })
+        Ok(Self { state, ids })
     }
 }
 
+fn state_ident(state: &syn::Path) -> syn::Ident {
+    state
+        .segments
+        .last()
+        .expect("state must be a path like State::Play")
+        .ident
+        .clone()
+}
+
 #[proc_macro_attribute]
 pub fn sb_packet(args: RawToken, st: RawToken) -> RawToken {
     let args = parse_macro_input!(args as SBPacketIdArgs);
     let st = parse_macro_input!(st as syn::ItemStruct);
 
     let ident = st.ident.clone();
-    let id = args.id;
+    let ids = match (&args.state, args.ids) {
+        (_, PacketIdSource::Ids(ids)) => Ok(ids),
+        (Some(state), ids) => ids.resolve(&state_ident(state), Direction::ServerBound),
+        (None, PacketIdSource::Name(name)) => Err(syn::Error::new(
+            name.name.span(),
+            "packet names need a state, like `State::Play, \"minecraft:chat\"`",
+        )),
+    };
+    let packet_id = match ids {
+        Ok(ids) => ids.packet_id_fn(),
+        Err(e) => return e.to_compile_error().into(),
+    };
 
     let token = quote! {
         #st
         impl ServerBoundPacket for #ident {
-            const PACKET_ID: i32 = #id;
+            #packet_id
         }
     };
 
@@ -36,17 +378,17 @@ pub fn sb_packet(args: RawToken, st: RawToken) -> RawToken {
 }
 
 struct CBPacketArgs {
-    state: syn::Expr,
-    id: syn::Expr,
+    state: syn::Path,
+    ids: PacketIdSource,
 }
 
 impl Parse for CBPacketArgs {
     fn parse(input: ParseStream) -> syn::Result<Self> {
-        let state = syn::Expr::parse(input)?;
+        let state = syn::Path::parse(input)?;
         let _comma = syn::token::Comma::parse(input)?;
-        let id = syn::Expr::parse(input)?;
+        let ids = PacketIdSource::parse(input)?;
 
-        Ok(Self { state, id })
+        Ok(Self { state, ids })
     }
 }
 
@@ -56,14 +398,18 @@ pub fn cb_packet(args: RawToken, st: RawToken) -> RawToken {
     let st = parse_macro_input!(st as syn::ItemStruct);
 
     let ident = st.ident.clone();
-    let state = args.state;
-    let id = args.id;
+    // `State::Play` is resolved to the `server_bound::Play` cluster, which is the state marker type.
+    let state = state_ident(&args.state);
+    let packet_id = match args.ids.resolve(&state, Direction::ClientBound) {
+        Ok(ids) => ids.packet_id_fn(),
+        Err(e) => return e.to_compile_error().into(),
+    };
 
     let token = quote! {
         #st
         impl ClientBoundPacket for #ident {
-            const PACKET_ID: i32 = #id;
-            const VALID_STATE: State = #state;
+            type ValidState = server_bound::#state;
+            #packet_id
         }
     };
 
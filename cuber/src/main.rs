@@ -3,172 +3,258 @@ pub mod protocol;
 use std::net::Ipv4Addr;
 
 use protocol::client_bound::{
-    ChangeDifficulty, ChunkDataAndUpdateLight, FeatureFlags, LoginPlay, LoginSuccess,
-    PlayerAbilities, PluginMessage, SetHeldItem, SpawnEntity, SynchronizePlayerPosition,
+    ChangeDifficulty, ChunkDataAndUpdateLight, ConfigFeatureFlags, Disconnect, FeatureFlags,
+    LightData, LoginPlay, LoginSuccess, PlayerAbilities, PluginMessage, RegistryData, SetHeldItem,
+    SpawnEntity, SynchronizePlayerPosition,
 };
 use protocol::common::{PlayerAbilitiesFlags, SynchronizePlayerPositionFlags};
-use protocol::primitive::Angle;
+use protocol::encryption::ServerKey;
+use protocol::legacy::LegacyStatus;
+use protocol::primitive::{Angle, Chat};
 use protocol::server_bound::HandshakeNextState;
-use protocol::Client;
-use tokio::net::TcpListener;
+use protocol::session::{login_offline, login_online, HttpSessionVerifier};
+use protocol::status::{handle_status, ServerStatus, StatusPlayers, StatusVersion};
+use protocol::version::ProtocolVersion;
+use protocol::{Client, LoginFinished};
+use tokio::net::{TcpListener, TcpStream};
 
 use anyhow::Result;
 
+const ONLINE_MODE: bool = false;
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let listener = TcpListener::bind((Ipv4Addr::new(127, 0, 0, 1), 25565)).await?;
 
+    let server_key = if ONLINE_MODE {
+        Some(ServerKey::generate()?)
+    } else {
+        None
+    };
+    let verifier = HttpSessionVerifier::new();
+
     while let Ok((socket, addr)) = listener.accept().await {
         println!("Connection from {addr}");
 
-        let mut client = Client::from_stream(socket);
-
-        let result = client
-            .receive_packet()
-            .await?
-            .as_handshaking()
-            .unwrap()
-            .unwrap_handshake();
-        if result.next_state != HandshakeNextState::Login {
-            continue;
+        if let Err(e) = handle_connection(socket, server_key.as_ref(), &verifier).await {
+            println!("Connection from {addr} is closed: {e:?}");
         }
-        client.set_state(protocol::common::State::Login);
+    }
 
-        let ls = client
-            .receive_packet()
-            .await?
-            .as_login()
-            .unwrap()
-            .assume_login_start()?;
+    Ok(())
+}
 
-        let player_uuid = ls.uuid.0.unwrap_or_default();
+async fn handle_connection(
+    socket: TcpStream,
+    server_key: Option<&ServerKey>,
+    verifier: &HttpSessionVerifier,
+) -> Result<()> {
+    let mut client = Client::from_stream(socket);
 
-        println!("New player!  name: {}, uuid: {:?}", ls.name, ls.uuid.0);
+    if client.is_legacy_ping().await? {
+        let ping = client.receive_legacy_ping().await?;
+        let status = LegacyStatus::from(&server_status(ProtocolVersion::DEFAULT));
+        client.send_legacy_status(&ping, &status).await?;
+        return Ok(());
+    }
 
-        let sc = LoginSuccess {
-            uuid: player_uuid.clone(),
-            user_name: ls.name,
-            property: vec![].into(),
-        };
-        client.send_packet(sc).await;
-
-        client.set_state(protocol::common::State::Play);
-
-        let login_play = LoginPlay {
-            entity_id: 0x11223344,
-            is_hardcore: false,
-            game_mode: protocol::common::GameMode::Creative,
-            previous_game_mode: protocol::common::GameMode::Undefined,
-            dimension_names: vec![
-                "minecraft:overworld".into(),
-                "minecraft:the_end".into(),
-                "minecraft:nether".into(),
-            ]
-            .into(),
-            registry_codec: read_mock_nbt_blob()?,
-            // registry_codec: Blob::new(),
-            dimension_type: "minecraft:overworld".into(),
-            dimension_name: "minecraft:overworld".into(),
-            hashed_seed: 0x100000,
-            max_players: 20.into(),
-            view_distance: 10.into(),
-            simulation_distance: 10.into(),
-            reduce_debug_info: false,
-            enable_respawn_screen: true,
-            is_debug: false,
-            is_flat: false,
-            death_location: None.into(),
-            portal_cooldown: 10.into(),
-        };
-        client.send_packet(login_play).await;
+    let result = client.receive_packet().await?.unwrap_handshake();
+    let protocol_version = ProtocolVersion::from(i32::from(result.protocol_version));
+    client.set_protocol_version(protocol_version);
+    if result.next_state == HandshakeNextState::Status {
+        let mut client = client.into_status();
+        return handle_status(&mut client, &server_status(protocol_version)).await;
+    }
+    let mut client = client.into_login();
+
+    if !protocol_version.is_supported() {
+        let supported = ProtocolVersion::SUPPORTED
+            .iter()
+            .filter_map(|version| version.name())
+            .collect::<Vec<_>>()
+            .join(", ");
+        client
+            .send_packet(Disconnect {
+                chat: Chat::text(&format!("Unsupported version! Please use {}", supported)),
+            })
+            .await?;
+        return Ok(());
+    }
+
+    let profile = match server_key {
+        Some(key) => login_online(&mut client, key, verifier).await?,
+        None => login_offline(&mut client).await?,
+    };
+
+    let player_uuid = profile.id;
 
+    println!("New player!  name: {}, uuid: {}", profile.name, profile.id);
+
+    client.set_compression(256).await?;
+
+    let registry_codec = read_mock_nbt_blob()?;
+
+    let sc = LoginSuccess::from(profile);
+    let mut client = match client.finish_login(sc).await? {
+        LoginFinished::Play(client) => client,
+        LoginFinished::Configuration(mut client) => {
+            client
+                .send_packet(RegistryData {
+                    registry_codec: registry_codec.clone().into(),
+                })
+                .await?;
+            client
+                .send_packet(ConfigFeatureFlags {
+                    features: vec![protocol::common::Feature::Vanilla].into(),
+                })
+                .await?;
+            client.finish_configuration().await?
+        }
+    };
+
+    let login_play = LoginPlay {
+        entity_id: 0x11223344,
+        is_hardcore: false,
+        game_mode: protocol::common::GameMode::Creative,
+        previous_game_mode: protocol::common::GameMode::Undefined,
+        dimension_names: vec![
+            "minecraft:overworld".into(),
+            "minecraft:the_end".into(),
+            "minecraft:nether".into(),
+        ]
+        .into(),
+        registry_codec: registry_codec.into(),
+        // registry_codec: Blob::new(),
+        dimension_type: "minecraft:overworld".into(),
+        dimension_name: "minecraft:overworld".into(),
+        hashed_seed: 0x100000,
+        max_players: 20.into(),
+        view_distance: 10.into(),
+        simulation_distance: 10.into(),
+        reduce_debug_info: false,
+        enable_respawn_screen: true,
+        do_limited_crafting: false,
+        is_debug: false,
+        is_flat: false,
+        death_location: None.into(),
+        portal_cooldown: 10.into(),
+    };
+    client.send_packet(login_play).await?;
+
+    // sent during the configuration since 1.20.2.
+    if protocol_version < ProtocolVersion::V1_20_2 {
         let features = FeatureFlags {
             features: vec![protocol::common::Feature::Vanilla].into(),
         };
-        client.send_packet(features).await;
+        client.send_packet(features).await?;
+    }
 
-        let pm = PluginMessage {
-            channel: "minecraft:brand".into(),
-            data: "vanilla".as_bytes().into(),
-        };
-        client.send_packet(pm).await;
+    let pm = PluginMessage {
+        channel: "minecraft:brand".into(),
+        data: "vanilla".as_bytes().into(),
+    };
+    client.send_packet(pm).await?;
 
-        let cd = ChangeDifficulty {
-            new_difficulty: protocol::common::Difficulty::Peaceful,
-            difficulty_locked: false,
-        };
-        client.send_packet(cd).await;
+    let cd = ChangeDifficulty {
+        new_difficulty: protocol::common::Difficulty::Peaceful,
+        difficulty_locked: false,
+    };
+    client.send_packet(cd).await?;
 
-        let pa = PlayerAbilities {
-            flags: PlayerAbilitiesFlags::CREATIVE_MODE,
-            flying_speed: 0.1,
-            field_of_view_modifier: 0.1,
-        };
-        client.send_packet(pa).await;
+    let pa = PlayerAbilities {
+        flags: PlayerAbilitiesFlags::CREATIVE_MODE,
+        flying_speed: 0.1,
+        field_of_view_modifier: 0.1,
+    };
+    client.send_packet(pa).await?;
+
+    let hi = SetHeldItem { slot: 0 };
+    client.send_packet(hi).await?;
+
+    let se = SpawnEntity {
+        entity_id: 0.into(),
+        entity_uuid: player_uuid,
+        mob_type: 0.into(),
+        x: 0.,
+        y: 0.,
+        z: 0.,
+        pitch: Angle { value: 0 },
+        yaw: Angle { value: 0 },
+        head_yaw: Angle { value: 0 },
+        data: 0.into(),
+        velocity_x: 0,
+        velocity_y: 0,
+        velocity_z: 0,
+    };
+    client.send_packet(se).await?;
+
+    let mut heightmaps = nbt::Blob::new();
+    heightmaps.insert(
+        "MOTION_BLOCKING",
+        nbt::Value::List(vec![nbt::Value::Long(0); 37]),
+    )?;
 
-        let hi = SetHeldItem { slot: 0 };
-        client.send_packet(hi).await;
+    let cdap = ChunkDataAndUpdateLight {
+        chunk_x: 0,
+        chunk_z: 0,
+        height_maps: heightmaps.into(),
+        chunk_data: vec![].into(),
+        block_entities: vec![].into(),
+        light: LightData::default(),
+    };
+    client.send_packet(cdap).await?;
 
-        let se = SpawnEntity {
-            entity_id: 0.into(),
-            entity_uuid: player_uuid.clone(),
-            mob_type: 0.into(),
+    loop {
+        let mut packet = match client.receive_raw_packet().await {
+            Ok(packet) => packet,
+            Err(e) if e.is::<std::io::Error>() => break, // disconnected
+            Err(e) => return Err(e),                     // the stream can not be framed anymore
+        };
+        if let Err(e) = client.parse_packet(&mut packet) {
+            println!("packet: {:?}", e);
+            continue;
+        }
+        if let Some(warning) = packet.take_warning() {
+            println!("warning: {}", warning);
+        }
+        // println!("packet: {:?}", packet);
+        let sync = SynchronizePlayerPosition {
             x: 0.,
             y: 0.,
             z: 0.,
-            pitch: Angle { value: 0 },
-            yaw: Angle { value: 0 },
-            head_yaw: Angle { value: 0 },
-            data: 0.into(),
-            velocity_x: 0,
-            velocity_y: 0,
-            velocity_z: 0,
-        };
-        client.send_packet(se).await;
-
-        let mut heightmaps = nbt::Blob::new();
-        heightmaps.insert(
-            "MOTION_BLOCKING",
-            nbt::Value::List(vec![nbt::Value::Long(0); 37]),
-        )?;
-
-        let cdap = ChunkDataAndUpdateLight {
-            chunk_x: 0,
-            chunk_z: 0,
-            height_maps: heightmaps,
-            chunk_data: vec![].into(),
-            block_entities: vec![].into(),
-            sky_light_mask: 0,
-            block_light_mask: 0,
-            empty_sky_light_mask: 0,
-            empty_block_light_mask: 0,
-            sky_lights_array: vec![].into(),
-            block_lights_array: vec![].into(),
+            yaw: 0.,
+            pitch: 0.,
+            flags: SynchronizePlayerPositionFlags::empty(),
+            teleport_id: 0.into(),
         };
-        client.send_packet(cdap).await;
-
-        loop {
-            let packet = client.receive_packet().await?.as_play();
-            if packet.is_err() {
-                println!("packet: {:?}", packet);
-                continue;
-            }
-            // println!("packet: {:?}", packet);
-            let sync = SynchronizePlayerPosition {
-                x: 0.,
-                y: 0.,
-                z: 0.,
-                yaw: 0.,
-                pitch: 0.,
-                flags: SynchronizePlayerPositionFlags::empty(),
-                teleport_id: 0.into(),
-            };
-            client.send_packet(sync).await;
-        }
+        client.send_packet(sync).await?;
     }
 
     Ok(())
 }
+// answers with the version of the client when it is supported, so that it is shown as compatible.
+fn server_status(protocol_version: ProtocolVersion) -> ServerStatus {
+    let version = if protocol_version.is_supported() {
+        protocol_version
+    } else {
+        ProtocolVersion::DEFAULT
+    };
+
+    ServerStatus {
+        version: StatusVersion {
+            name: version.name().unwrap_or_default().into(),
+            protocol: version.0,
+        },
+        players: Some(StatusPlayers {
+            max: 20,
+            online: 0,
+            sample: vec![],
+        }),
+        description: Some(Chat::text("A cuber server")),
+        favicon: None,
+        enforces_secure_chat: false,
+    }
+}
 fn read_mock_nbt_blob() -> std::io::Result<nbt::Blob> {
     use std::fs;
 
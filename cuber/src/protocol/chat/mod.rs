@@ -0,0 +1,1018 @@
+use std::{fmt, str::FromStr};
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use uuid::Uuid;
+
+use anyhow::{bail, ensure, Context as _, Result};
+
+pub const LEGACY_CODE_PREFIX: char = '\u{a7}';
+
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub struct TextComponent {
+    pub content: Content,
+    pub style: Style,
+    pub extra: Vec<TextComponent>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub enum Content {
+    Text(String),
+    Translate {
+        key: String,
+        with: Vec<TextComponent>,
+    },
+    Score(Score),
+    Selector {
+        selector: String,
+        separator: Option<Box<TextComponent>>,
+    },
+    Keybind(String),
+    Nbt {
+        path: String,
+        interpret: Option<bool>,
+        separator: Option<Box<TextComponent>>,
+        source: NbtSource,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Hash)]
+pub struct Score {
+    pub name: String,
+    pub objective: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub enum NbtSource {
+    Block(String),
+    Entity(String),
+    Storage(String),
+}
+
+// `None` inherits from the parent component.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq, Clone, Hash)]
+#[serde(rename_all = "camelCase")]
+pub struct Style {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<TextColor>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bold: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub italic: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub underlined: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strikethrough: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub obfuscated: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub font: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub insertion: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub click_event: Option<ClickEvent>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hover_event: Option<HoverEvent>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Hash)]
+#[serde(tag = "action", content = "value", rename_all = "snake_case")]
+pub enum ClickEvent {
+    OpenUrl(String),
+    OpenFile(String),
+    RunCommand(String),
+    SuggestCommand(String),
+    ChangePage(String),
+    CopyToClipboard(String),
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Hash)]
+#[serde(tag = "action", content = "contents", rename_all = "snake_case")]
+pub enum HoverEvent {
+    ShowText(Box<TextComponent>),
+    ShowItem(HoverItem),
+    ShowEntity(HoverEntity),
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Hash)]
+pub struct HoverItem {
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub count: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>, // SNBT
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Hash)]
+pub struct HoverEntity {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub id: Uuid,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<Box<TextComponent>>,
+}
+
+macro_rules! define_named_colors {
+    { $($variant: ident => ($name: literal, $code: literal, $rgb: literal),)* } => {
+        #[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+        pub enum NamedColor {
+            $($variant,)*
+        }
+
+        impl NamedColor {
+            pub const ALL: &'static [NamedColor] = &[$(Self::$variant,)*];
+
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(Self::$variant => $name,)*
+                }
+            }
+            pub fn code(self) -> char {
+                match self {
+                    $(Self::$variant => $code,)*
+                }
+            }
+            pub fn rgb(self) -> u32 {
+                match self {
+                    $(Self::$variant => $rgb,)*
+                }
+            }
+            pub fn from_name(name: &str) -> Option<Self> {
+                match name {
+                    $($name => Some(Self::$variant),)*
+                    _ => None,
+                }
+            }
+            pub fn from_code(code: char) -> Option<Self> {
+                match code.to_ascii_lowercase() {
+                    $($code => Some(Self::$variant),)*
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+define_named_colors! {
+    Black => ("black", '0', 0x000000),
+    DarkBlue => ("dark_blue", '1', 0x0000aa),
+    DarkGreen => ("dark_green", '2', 0x00aa00),
+    DarkAqua => ("dark_aqua", '3', 0x00aaaa),
+    DarkRed => ("dark_red", '4', 0xaa0000),
+    DarkPurple => ("dark_purple", '5', 0xaa00aa),
+    Gold => ("gold", '6', 0xffaa00),
+    Gray => ("gray", '7', 0xaaaaaa),
+    DarkGray => ("dark_gray", '8', 0x555555),
+    Blue => ("blue", '9', 0x5555ff),
+    Green => ("green", 'a', 0x55ff55),
+    Aqua => ("aqua", 'b', 0x55ffff),
+    Red => ("red", 'c', 0xff5555),
+    LightPurple => ("light_purple", 'd', 0xff55ff),
+    Yellow => ("yellow", 'e', 0xffff55),
+    White => ("white", 'f', 0xffffff),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum TextColor {
+    Named(NamedColor),
+    Hex(u32),
+}
+
+impl TextColor {
+    // legacy codes only have the 16 named colors, so hex colors are rounded to the closest one.
+    pub fn to_named(self) -> NamedColor {
+        match self {
+            Self::Named(named) => named,
+            Self::Hex(rgb) => {
+                let distance = |other: u32| {
+                    [16, 8, 0]
+                        .iter()
+                        .map(|shift| {
+                            let diff =
+                                ((rgb >> shift) & 0xff) as i32 - ((other >> shift) & 0xff) as i32;
+                            diff * diff
+                        })
+                        .sum::<i32>()
+                };
+                *NamedColor::ALL
+                    .iter()
+                    .min_by_key(|named| distance(named.rgb()))
+                    .unwrap()
+            }
+        }
+    }
+}
+
+impl From<NamedColor> for TextColor {
+    fn from(value: NamedColor) -> Self {
+        Self::Named(value)
+    }
+}
+
+impl fmt::Display for TextColor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Named(named) => f.write_str(named.name()),
+            Self::Hex(rgb) => write!(f, "#{:06X}", rgb),
+        }
+    }
+}
+
+impl FromStr for TextColor {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.strip_prefix('#') {
+            Some(hex) => {
+                ensure!(hex.len() == 6, "Invalid hex color: {}", s);
+                let rgb = u32::from_str_radix(hex, 16)
+                    .with_context(|| format!("Invalid hex color: {}", s))?;
+                Ok(Self::Hex(rgb))
+            }
+            None => match NamedColor::from_name(s) {
+                Some(named) => Ok(Self::Named(named)),
+                None => bail!("Unknown color: {}", s),
+            },
+        }
+    }
+}
+
+impl Serialize for TextColor {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for TextColor {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+impl TextComponent {
+    pub fn new(content: Content) -> Self {
+        Self {
+            content,
+            style: Style::default(),
+            extra: vec![],
+        }
+    }
+    pub fn text(text: impl Into<String>) -> Self {
+        Self::new(Content::Text(text.into()))
+    }
+    pub fn translate(key: impl Into<String>, with: Vec<TextComponent>) -> Self {
+        Self::new(Content::Translate {
+            key: key.into(),
+            with,
+        })
+    }
+    pub fn score(name: impl Into<String>, objective: impl Into<String>) -> Self {
+        Self::new(Content::Score(Score {
+            name: name.into(),
+            objective: objective.into(),
+        }))
+    }
+    pub fn selector(selector: impl Into<String>) -> Self {
+        Self::new(Content::Selector {
+            selector: selector.into(),
+            separator: None,
+        })
+    }
+    pub fn keybind(key: impl Into<String>) -> Self {
+        Self::new(Content::Keybind(key.into()))
+    }
+    pub fn nbt(path: impl Into<String>, source: NbtSource) -> Self {
+        Self::new(Content::Nbt {
+            path: path.into(),
+            interpret: None,
+            separator: None,
+            source,
+        })
+    }
+
+    pub fn style(mut self, style: Style) -> Self {
+        self.style = style;
+        self
+    }
+    pub fn color(mut self, color: impl Into<TextColor>) -> Self {
+        self.style.color = Some(color.into());
+        self
+    }
+    pub fn bold(mut self, bold: bool) -> Self {
+        self.style.bold = Some(bold);
+        self
+    }
+    pub fn italic(mut self, italic: bool) -> Self {
+        self.style.italic = Some(italic);
+        self
+    }
+    pub fn underlined(mut self, underlined: bool) -> Self {
+        self.style.underlined = Some(underlined);
+        self
+    }
+    pub fn strikethrough(mut self, strikethrough: bool) -> Self {
+        self.style.strikethrough = Some(strikethrough);
+        self
+    }
+    pub fn obfuscated(mut self, obfuscated: bool) -> Self {
+        self.style.obfuscated = Some(obfuscated);
+        self
+    }
+    pub fn font(mut self, font: impl Into<String>) -> Self {
+        self.style.font = Some(font.into());
+        self
+    }
+    pub fn insertion(mut self, insertion: impl Into<String>) -> Self {
+        self.style.insertion = Some(insertion.into());
+        self
+    }
+    pub fn click_event(mut self, event: ClickEvent) -> Self {
+        self.style.click_event = Some(event);
+        self
+    }
+    pub fn hover_event(mut self, event: HoverEvent) -> Self {
+        self.style.hover_event = Some(event);
+        self
+    }
+    pub fn append(mut self, child: impl Into<TextComponent>) -> Self {
+        self.extra.push(child.into());
+        self
+    }
+
+    // the text a client would show, without styles. score, selector and nbt need the world to resolve.
+    pub fn to_plain(&self) -> String {
+        let mut out = String::new();
+        self.write_plain(&mut out);
+        out
+    }
+    fn write_plain(&self, out: &mut String) {
+        out.push_str(&self.content.to_plain());
+        for child in &self.extra {
+            child.write_plain(out);
+        }
+    }
+
+    pub fn to_legacy(&self) -> String {
+        let mut out = String::new();
+        self.write_legacy(&Style::default(), &mut LegacyFormat::default(), &mut out);
+        out
+    }
+    fn write_legacy(&self, parent: &Style, last: &mut LegacyFormat, out: &mut String) {
+        let style = self.style.inherit(parent);
+
+        let text = self.content.to_plain();
+        if !text.is_empty() {
+            let format = LegacyFormat::of(&style);
+            if format != *last {
+                format.write_codes(last, out);
+                *last = format;
+            }
+            out.push_str(&text);
+        }
+
+        for child in &self.extra {
+            child.write_legacy(&style, last, out);
+        }
+    }
+
+    // unknown codes are dropped, like the vanilla client does.
+    pub fn from_legacy(legacy: &str) -> Self {
+        let mut segments = vec![];
+        let mut style = Style::default();
+        let mut text = String::new();
+
+        let mut chars = legacy.chars();
+        while let Some(c) = chars.next() {
+            if c != LEGACY_CODE_PREFIX {
+                text.push(c);
+                continue;
+            }
+            let Some(code) = chars.next() else {
+                break;
+            };
+
+            let mut next = style.clone();
+            match code.to_ascii_lowercase() {
+                'k' => next.obfuscated = Some(true),
+                'l' => next.bold = Some(true),
+                'm' => next.strikethrough = Some(true),
+                'n' => next.underlined = Some(true),
+                'o' => next.italic = Some(true),
+                'r' => next = Style::default(),
+                code => match NamedColor::from_code(code) {
+                    // a color code also resets the formats.
+                    Some(named) => {
+                        next = Style {
+                            color: Some(named.into()),
+                            ..Default::default()
+                        }
+                    }
+                    None => continue,
+                },
+            }
+
+            if next != style {
+                if !text.is_empty() {
+                    segments.push(Self::text(std::mem::take(&mut text)).style(style));
+                }
+                style = next;
+            }
+        }
+        if !text.is_empty() {
+            segments.push(Self::text(text).style(style));
+        }
+
+        match segments.len() {
+            0 => Self::text(""),
+            1 if segments[0].style == Style::default() => segments.pop().unwrap(),
+            _ => Self {
+                extra: segments,
+                ..Self::text("")
+            },
+        }
+    }
+}
+
+impl Content {
+    fn to_plain(&self) -> String {
+        match self {
+            Self::Text(text) => text.clone(),
+            Self::Translate { key, with } => {
+                // only the `%s` and `%1$s` placeholders, translations are on the client.
+                let mut out = String::new();
+                let mut next = 0;
+                let mut rest = key.as_str();
+                while let Some(position) = rest.find('%') {
+                    out.push_str(&rest[..position]);
+                    rest = &rest[position + 1..];
+
+                    if let Some(after) = rest.strip_prefix('s') {
+                        out.push_str(&with.get(next).map(|arg| arg.to_plain()).unwrap_or_default());
+                        next += 1;
+                        rest = after;
+                    } else if let Some(after) = rest.strip_prefix('%') {
+                        out.push('%');
+                        rest = after;
+                    } else if let Some((index, after)) = rest.split_once("$s") {
+                        match index.parse::<usize>() {
+                            Ok(index) if index > 0 => {
+                                out.push_str(
+                                    &with
+                                        .get(index - 1)
+                                        .map(|arg| arg.to_plain())
+                                        .unwrap_or_default(),
+                                );
+                                rest = after;
+                            }
+                            _ => out.push('%'),
+                        }
+                    } else {
+                        out.push('%');
+                    }
+                }
+                out.push_str(rest);
+                out
+            }
+            Self::Keybind(key) => key.clone(),
+            Self::Selector { selector, .. } => selector.clone(),
+            Self::Score(_) | Self::Nbt { .. } => String::new(),
+        }
+    }
+}
+
+impl Style {
+    pub fn inherit(&self, parent: &Style) -> Style {
+        Style {
+            color: self.color.or(parent.color),
+            bold: self.bold.or(parent.bold),
+            italic: self.italic.or(parent.italic),
+            underlined: self.underlined.or(parent.underlined),
+            strikethrough: self.strikethrough.or(parent.strikethrough),
+            obfuscated: self.obfuscated.or(parent.obfuscated),
+            font: self.font.clone().or_else(|| parent.font.clone()),
+            insertion: self.insertion.clone().or_else(|| parent.insertion.clone()),
+            click_event: self
+                .click_event
+                .clone()
+                .or_else(|| parent.click_event.clone()),
+            hover_event: self
+                .hover_event
+                .clone()
+                .or_else(|| parent.hover_event.clone()),
+        }
+    }
+}
+
+// the part of a style that legacy codes can express.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+struct LegacyFormat {
+    color: Option<NamedColor>,
+    obfuscated: bool,
+    bold: bool,
+    strikethrough: bool,
+    underlined: bool,
+    italic: bool,
+}
+
+impl LegacyFormat {
+    fn of(style: &Style) -> Self {
+        Self {
+            color: style.color.map(TextColor::to_named),
+            obfuscated: style.obfuscated.unwrap_or(false),
+            bold: style.bold.unwrap_or(false),
+            strikethrough: style.strikethrough.unwrap_or(false),
+            underlined: style.underlined.unwrap_or(false),
+            italic: style.italic.unwrap_or(false),
+        }
+    }
+    fn flags(&self) -> [(bool, char); 5] {
+        [
+            (self.obfuscated, 'k'),
+            (self.bold, 'l'),
+            (self.strikethrough, 'm'),
+            (self.underlined, 'n'),
+            (self.italic, 'o'),
+        ]
+    }
+    fn write_codes(&self, last: &LegacyFormat, out: &mut String) {
+        let mut push = |code: char| {
+            out.push(LEGACY_CODE_PREFIX);
+            out.push(code);
+        };
+
+        // formats can only be added, so anything else starts over from a color or a reset.
+        let only_added = self.color == last.color
+            && last
+                .flags()
+                .iter()
+                .zip(self.flags())
+                .all(|((was, _), (now, _))| !was || now);
+        if !only_added {
+            push(self.color.map_or('r', NamedColor::code));
+        }
+
+        for ((was, _), (now, code)) in last.flags().iter().zip(self.flags()) {
+            if now && (!was || !only_added) {
+                push(code);
+            }
+        }
+    }
+}
+
+// 1.20.3+ sends components as NBT, with the same shape as the JSON form.
+impl TextComponent {
+    pub fn to_nbt(&self) -> Result<nbt::Value> {
+        json_to_nbt(serde_json::to_value(self).context("Failed to serialize component")?)
+    }
+    pub fn from_nbt(value: &nbt::Value) -> Result<Self> {
+        serde_json::from_value(nbt_to_json(value)).context("Failed to parse component")
+    }
+}
+
+fn json_to_nbt(value: serde_json::Value) -> Result<nbt::Value> {
+    use serde_json::Value;
+
+    Ok(match value {
+        Value::Null => bail!("null can not be NBT"),
+        Value::Bool(b) => nbt::Value::Byte(b as i8),
+        Value::Number(n) => match (n.as_i64(), n.as_f64()) {
+            (Some(i), _) => match i32::try_from(i) {
+                Ok(i) => nbt::Value::Int(i),
+                Err(_) => nbt::Value::Long(i),
+            },
+            (None, Some(f)) => nbt::Value::Double(f),
+            _ => bail!("Unsupported number: {}", n),
+        },
+        Value::String(s) => nbt::Value::String(s),
+        Value::Array(values) => {
+            nbt::Value::List(values.into_iter().map(json_to_nbt).collect::<Result<_>>()?)
+        }
+        // a null member is an absent optional field, which NBT leaves out
+        Value::Object(object) => nbt::Value::Compound(
+            object
+                .into_iter()
+                .filter(|(_, value)| !value.is_null())
+                .map(|(key, value)| Ok((key, json_to_nbt(value)?)))
+                .collect::<Result<_>>()?,
+        ),
+    })
+}
+
+// bytes are read back as booleans, that is all they are used for in components.
+fn nbt_to_json(value: &nbt::Value) -> serde_json::Value {
+    use serde_json::Value;
+
+    match value {
+        nbt::Value::Byte(b @ (0 | 1)) => Value::Bool(*b == 1),
+        nbt::Value::Byte(b) => (*b).into(),
+        nbt::Value::Short(s) => (*s).into(),
+        nbt::Value::Int(i) => (*i).into(),
+        nbt::Value::Long(l) => (*l).into(),
+        nbt::Value::Float(f) => (*f).into(),
+        nbt::Value::Double(d) => (*d).into(),
+        nbt::Value::String(s) => s.clone().into(),
+        nbt::Value::ByteArray(values) => values.clone().into(),
+        nbt::Value::IntArray(values) => values.clone().into(),
+        nbt::Value::LongArray(values) => values.clone().into(),
+        nbt::Value::List(values) => values.iter().map(nbt_to_json).collect(),
+        nbt::Value::Compound(compound) => Value::Object(
+            compound
+                .iter()
+                .map(|(key, value)| (key.clone(), nbt_to_json(value)))
+                .collect(),
+        ),
+    }
+}
+
+impl From<&str> for TextComponent {
+    fn from(value: &str) -> Self {
+        Self::text(value)
+    }
+}
+
+impl From<String> for TextComponent {
+    fn from(value: String) -> Self {
+        Self::text(value)
+    }
+}
+
+// the JSON shape, where the kind of content is decided by which keys are present.
+#[derive(Serialize, Deserialize, Default)]
+struct RawComponent {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    translate: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    with: Vec<TextComponent>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    score: Option<Score>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    selector: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    separator: Option<Box<TextComponent>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    keybind: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    nbt: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    interpret: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    block: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    entity: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    storage: Option<String>,
+    #[serde(flatten)]
+    style: Style,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    extra: Vec<TextComponent>,
+}
+
+impl From<&TextComponent> for RawComponent {
+    fn from(value: &TextComponent) -> Self {
+        let mut raw = RawComponent {
+            style: value.style.clone(),
+            extra: value.extra.clone(),
+            ..Default::default()
+        };
+
+        match value.content.clone() {
+            Content::Text(text) => raw.text = Some(text),
+            Content::Translate { key, with } => {
+                raw.translate = Some(key);
+                raw.with = with;
+            }
+            Content::Score(score) => raw.score = Some(score),
+            Content::Selector {
+                selector,
+                separator,
+            } => {
+                raw.selector = Some(selector);
+                raw.separator = separator;
+            }
+            Content::Keybind(key) => raw.keybind = Some(key),
+            Content::Nbt {
+                path,
+                interpret,
+                separator,
+                source,
+            } => {
+                raw.nbt = Some(path);
+                raw.interpret = interpret;
+                raw.separator = separator;
+                match source {
+                    NbtSource::Block(block) => raw.block = Some(block),
+                    NbtSource::Entity(entity) => raw.entity = Some(entity),
+                    NbtSource::Storage(storage) => raw.storage = Some(storage),
+                }
+            }
+        }
+
+        raw
+    }
+}
+
+impl TryFrom<RawComponent> for TextComponent {
+    type Error = String;
+
+    fn try_from(raw: RawComponent) -> std::result::Result<Self, Self::Error> {
+        let content = if let Some(text) = raw.text {
+            Content::Text(text)
+        } else if let Some(key) = raw.translate {
+            Content::Translate {
+                key,
+                with: raw.with,
+            }
+        } else if let Some(score) = raw.score {
+            Content::Score(score)
+        } else if let Some(selector) = raw.selector {
+            Content::Selector {
+                selector,
+                separator: raw.separator,
+            }
+        } else if let Some(key) = raw.keybind {
+            Content::Keybind(key)
+        } else if let Some(path) = raw.nbt {
+            let source = match (raw.block, raw.entity, raw.storage) {
+                (Some(block), _, _) => NbtSource::Block(block),
+                (_, Some(entity), _) => NbtSource::Entity(entity),
+                (_, _, Some(storage)) => NbtSource::Storage(storage),
+                _ => return Err(format!("nbt component without source: {}", path)),
+            };
+            Content::Nbt {
+                path,
+                interpret: raw.interpret,
+                separator: raw.separator,
+                source,
+            }
+        } else {
+            return Err("component without content".to_string());
+        };
+
+        Ok(Self {
+            content,
+            style: raw.style,
+            extra: raw.extra,
+        })
+    }
+}
+
+impl Serialize for TextComponent {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        RawComponent::from(self).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for TextComponent {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        // a plain string and a list (the first element being the parent) are shorthands.
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum AnyComponent {
+            Text(String),
+            List(Vec<TextComponent>),
+            Object(Box<RawComponent>),
+        }
+
+        match AnyComponent::deserialize(deserializer)? {
+            AnyComponent::Text(text) => Ok(Self::text(text)),
+            AnyComponent::List(list) => {
+                let mut list = list.into_iter();
+                let mut parent = list
+                    .next()
+                    .ok_or_else(|| de::Error::custom("empty component list"))?;
+                parent.extra.extend(list);
+                Ok(parent)
+            }
+            AnyComponent::Object(raw) => Self::try_from(*raw).map_err(de::Error::custom),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn round_trip(component: &TextComponent) -> serde_json::Value {
+        let json = serde_json::to_value(component).unwrap();
+        assert_eq!(
+            &serde_json::from_value::<TextComponent>(json.clone()).unwrap(),
+            component
+        );
+        json
+    }
+
+    #[test]
+    fn text_json() {
+        let component = TextComponent::text("Hello, ")
+            .color(NamedColor::Gold)
+            .bold(true)
+            .append(
+                TextComponent::text("world")
+                    .color(TextColor::Hex(0x12ab34))
+                    .italic(true),
+            );
+
+        assert_eq!(
+            round_trip(&component),
+            json!({
+                "text": "Hello, ",
+                "color": "gold",
+                "bold": true,
+                "extra": [{ "text": "world", "color": "#12AB34", "italic": true }],
+            })
+        );
+    }
+
+    #[test]
+    fn contents_json() {
+        assert_eq!(
+            round_trip(&TextComponent::translate(
+                "chat.type.text",
+                vec!["Notch".into(), "hi".into()]
+            )),
+            json!({ "translate": "chat.type.text", "with": [{ "text": "Notch" }, { "text": "hi" }] })
+        );
+        assert_eq!(
+            round_trip(&TextComponent::score("@p", "kills")),
+            json!({ "score": { "name": "@p", "objective": "kills" } })
+        );
+        assert_eq!(
+            round_trip(&TextComponent::selector("@a")),
+            json!({ "selector": "@a" })
+        );
+        assert_eq!(
+            round_trip(&TextComponent::keybind("key.jump")),
+            json!({ "keybind": "key.jump" })
+        );
+        assert_eq!(
+            round_trip(&TextComponent::nbt(
+                "Items",
+                NbtSource::Block("0 64 0".into())
+            )),
+            json!({ "nbt": "Items", "block": "0 64 0" })
+        );
+    }
+
+    #[test]
+    fn events_json() {
+        let id = Uuid::parse_str("069a79f4-44e9-4726-a5be-fca90e38aaf5").unwrap();
+        let component = TextComponent::text("click")
+            .font("minecraft:uniform")
+            .insertion("inserted")
+            .click_event(ClickEvent::OpenUrl("https://example.com".into()))
+            .hover_event(HoverEvent::ShowEntity(HoverEntity {
+                kind: "minecraft:player".into(),
+                id,
+                name: Some(Box::new("Notch".into())),
+            }));
+
+        assert_eq!(
+            round_trip(&component),
+            json!({
+                "text": "click",
+                "font": "minecraft:uniform",
+                "insertion": "inserted",
+                "clickEvent": { "action": "open_url", "value": "https://example.com" },
+                "hoverEvent": {
+                    "action": "show_entity",
+                    "contents": {
+                        "type": "minecraft:player",
+                        "id": "069a79f4-44e9-4726-a5be-fca90e38aaf5",
+                        "name": { "text": "Notch" },
+                    },
+                },
+            })
+        );
+
+        let component = TextComponent::text("item").hover_event(HoverEvent::ShowItem(HoverItem {
+            id: "minecraft:stone".into(),
+            count: Some(2),
+            tag: None,
+        }));
+        assert_eq!(
+            round_trip(&component)["hoverEvent"],
+            json!({ "action": "show_item", "contents": { "id": "minecraft:stone", "count": 2 } })
+        );
+    }
+
+    #[test]
+    fn shorthand_json() {
+        let parsed: TextComponent = serde_json::from_value(json!("plain")).unwrap();
+        assert_eq!(parsed, TextComponent::text("plain"));
+
+        let parsed: TextComponent =
+            serde_json::from_value(json!(["a", { "text": "b", "color": "red" }])).unwrap();
+        assert_eq!(
+            parsed,
+            TextComponent::text("a").append(TextComponent::text("b").color(NamedColor::Red))
+        );
+
+        assert!(serde_json::from_value::<TextComponent>(json!({ "color": "red" })).is_err());
+        assert!(
+            serde_json::from_value::<TextComponent>(json!({ "text": "a", "color": "#12" }))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn nbt_round_trip() {
+        let component = TextComponent::translate("chat.type.text", vec!["Notch".into()])
+            .color(NamedColor::Gray)
+            .italic(false)
+            .hover_event(HoverEvent::ShowItem(HoverItem {
+                id: "minecraft:stone".into(),
+                count: Some(2),
+                tag: None,
+            }));
+
+        let nbt = component.to_nbt().unwrap();
+        let nbt::Value::Compound(compound) = &nbt else {
+            panic!("not a compound: {:?}", nbt);
+        };
+        assert_eq!(compound["italic"], nbt::Value::Byte(0));
+        assert_eq!(compound["color"], nbt::Value::String("gray".into()));
+        assert_eq!(TextComponent::from_nbt(&nbt).unwrap(), component);
+
+        // plain text may be sent as a bare string tag.
+        assert_eq!(
+            TextComponent::from_nbt(&nbt::Value::String("plain".into())).unwrap(),
+            TextComponent::text("plain")
+        );
+
+        // an absent separator is left out, rather than failing on the null.
+        let selector = TextComponent::selector("@p");
+        let nbt = selector.to_nbt().unwrap();
+        let nbt::Value::Compound(compound) = &nbt else {
+            panic!("not a compound: {:?}", nbt);
+        };
+        assert!(!compound.contains_key("separator"));
+        assert_eq!(TextComponent::from_nbt(&nbt).unwrap(), selector);
+    }
+
+    #[test]
+    fn to_plain() {
+        let component = TextComponent::translate(
+            "%s says %2$s, 100%%",
+            vec!["Notch".into(), TextComponent::text("hi").bold(true)],
+        )
+        .append("!");
+
+        assert_eq!(component.to_plain(), "Notch says hi, 100%!");
+    }
+
+    #[test]
+    fn to_legacy() {
+        let component = TextComponent::text("a")
+            .color(NamedColor::Red)
+            .append(TextComponent::text("b").bold(true))
+            .append(TextComponent::text("c").color(NamedColor::Blue))
+            .append(TextComponent::text("d").color(TextColor::Hex(0xfe5656)))
+            .append(
+                TextComponent::text("e")
+                    .color(TextColor::Hex(0xfe5656))
+                    .bold(false),
+            );
+
+        assert_eq!(component.to_legacy(), "\u{a7}ca\u{a7}lb\u{a7}9c\u{a7}cde");
+        assert_eq!(
+            TextComponent::text("plain")
+                .append(TextComponent::text("bold").bold(true))
+                .to_legacy(),
+            "plain\u{a7}lbold"
+        );
+        assert_eq!(
+            TextComponent::text("")
+                .append(TextComponent::text("bold").bold(true))
+                .append("plain")
+                .to_legacy(),
+            "\u{a7}lbold\u{a7}rplain"
+        );
+    }
+
+    #[test]
+    fn from_legacy() {
+        assert_eq!(
+            TextComponent::from_legacy("plain"),
+            TextComponent::text("plain")
+        );
+
+        let component =
+            TextComponent::from_legacy("\u{a7}cred \u{a7}lbold\u{a7}r plain\u{a7}z\u{a7}");
+        assert_eq!(
+            component,
+            TextComponent::text("")
+                .append(TextComponent::text("red ").color(NamedColor::Red))
+                .append(
+                    TextComponent::text("bold")
+                        .color(NamedColor::Red)
+                        .bold(true)
+                )
+                .append(" plain")
+        );
+        assert_eq!(component.to_legacy(), "\u{a7}cred \u{a7}lbold\u{a7}r plain");
+    }
+}
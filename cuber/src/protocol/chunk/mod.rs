@@ -0,0 +1,516 @@
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+    marker::PhantomData,
+};
+
+use super::{
+    primitive::{
+        array::{Array, VarIntLength},
+        VarInt,
+    },
+    Decodable, Encodable,
+};
+
+use anyhow::{bail, ensure, Context as _, Result};
+
+// what a container holds. the bits are the ones the vanilla client expects for each range.
+pub trait PaletteKind {
+    const EDGE: usize; // entries along each axis
+    const MIN_INDIRECT_BITS: u8;
+    const MAX_INDIRECT_BITS: u8;
+    const GLOBAL_BITS: u8; // bits of the direct palette, enough for every id of the registry
+
+    const ENTRIES: usize = Self::EDGE * Self::EDGE * Self::EDGE;
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub struct BlockStates;
+impl PaletteKind for BlockStates {
+    const EDGE: usize = 16;
+    const MIN_INDIRECT_BITS: u8 = 4;
+    const MAX_INDIRECT_BITS: u8 = 8;
+    const GLOBAL_BITS: u8 = 15;
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub struct Biomes;
+impl PaletteKind for Biomes {
+    const EDGE: usize = 4;
+    const MIN_INDIRECT_BITS: u8 = 1;
+    const MAX_INDIRECT_BITS: u8 = 3;
+    const GLOBAL_BITS: u8 = 6;
+}
+
+// linear and hashmap are the same indirect palette on the wire, and differ only in how an id is looked up.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Palette {
+    Single(u32),
+    Linear(Vec<u32>),
+    HashMap(Vec<u32>, HashMap<u32, u32>),
+    Global,
+}
+
+impl Palette {
+    fn indirect(ids: Vec<u32>, bits: u8) -> Self {
+        // as the vanilla client, searching is cheap enough up to 4 bits.
+        if bits <= 4 {
+            Self::Linear(ids)
+        } else {
+            let index = ids
+                .iter()
+                .enumerate()
+                .map(|(i, &id)| (id, i as u32))
+                .collect();
+            Self::HashMap(ids, index)
+        }
+    }
+    fn index_of(&self, id: u32) -> Option<u32> {
+        match self {
+            Self::Single(value) => (*value == id).then_some(0),
+            Self::Linear(ids) => ids.iter().position(|&i| i == id).map(|i| i as u32),
+            Self::HashMap(_, index) => index.get(&id).copied(),
+            Self::Global => Some(id),
+        }
+    }
+    fn id_of(&self, index: u32) -> u32 {
+        match self {
+            Self::Single(value) => *value,
+            Self::Linear(ids) | Self::HashMap(ids, _) => ids[index as usize],
+            Self::Global => index,
+        }
+    }
+    // adds `id` if there is room for it within `bits`.
+    fn try_insert(&mut self, id: u32, bits: u8) -> Option<u32> {
+        let ids = match self {
+            Self::Linear(ids) | Self::HashMap(ids, _) => ids,
+            Self::Single(_) | Self::Global => return None,
+        };
+        if ids.len() >= 1 << bits {
+            return None;
+        }
+        let index = ids.len() as u32;
+        ids.push(id);
+        if let Self::HashMap(_, map) = self {
+            map.insert(id, index);
+        }
+        Some(index)
+    }
+}
+
+// values packed into longs from the lowest bits, without spanning two longs (since 1.16).
+#[derive(Debug, PartialEq, Eq, Clone)]
+struct PackedArray {
+    bits: u8,
+    data: Vec<u64>,
+}
+
+impl PackedArray {
+    fn new(bits: u8, entries: usize) -> Self {
+        Self {
+            bits,
+            data: vec![0; Self::long_count(bits, entries)],
+        }
+    }
+    fn long_count(bits: u8, entries: usize) -> usize {
+        if bits == 0 {
+            0
+        } else {
+            entries.div_ceil(64 / bits as usize)
+        }
+    }
+    fn position(&self, index: usize) -> (usize, usize) {
+        let per_long = 64 / self.bits as usize;
+        (index / per_long, (index % per_long) * self.bits as usize)
+    }
+    fn mask(&self) -> u64 {
+        (1 << self.bits) - 1
+    }
+    fn get(&self, index: usize) -> u32 {
+        if self.bits == 0 {
+            return 0;
+        }
+        let (long, shift) = self.position(index);
+        ((self.data[long] >> shift) & self.mask()) as u32
+    }
+    fn set(&mut self, index: usize, value: u32) {
+        if self.bits == 0 {
+            return;
+        }
+        let (long, shift) = self.position(index);
+        let mask = self.mask();
+        self.data[long] = (self.data[long] & !(mask << shift)) | ((value as u64 & mask) << shift);
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct PalettedContainer<K> {
+    palette: Palette,
+    storage: PackedArray,
+    _kind: PhantomData<fn() -> K>,
+}
+
+impl<K: PaletteKind> PalettedContainer<K> {
+    // every entry is `value`.
+    pub fn new(value: u32) -> Self {
+        Self::check_id(value);
+        Self {
+            palette: Palette::Single(value),
+            storage: PackedArray::new(0, K::ENTRIES),
+            _kind: PhantomData,
+        }
+    }
+    // `values` are in the order of `index`, and there have to be exactly `K::ENTRIES` of them.
+    pub fn from_values(values: &[u32]) -> Self {
+        assert_eq!(
+            values.len(),
+            K::ENTRIES,
+            "paletted container length mismatch"
+        );
+
+        let mut ids = Vec::new();
+        for &value in values {
+            Self::check_id(value);
+            if !ids.contains(&value) {
+                ids.push(value);
+            }
+        }
+
+        let bits = Self::bits_for(ids.len());
+        let palette = match bits {
+            0 => return Self::new(ids[0]),
+            bits if bits == K::GLOBAL_BITS => Palette::Global,
+            bits => Palette::indirect(ids, bits),
+        };
+        let mut storage = PackedArray::new(bits, K::ENTRIES);
+        for (index, &value) in values.iter().enumerate() {
+            storage.set(index, palette.index_of(value).unwrap());
+        }
+
+        Self {
+            palette,
+            storage,
+            _kind: PhantomData,
+        }
+    }
+    pub fn index(x: usize, y: usize, z: usize) -> usize {
+        assert!(x < K::EDGE && y < K::EDGE && z < K::EDGE);
+        (y * K::EDGE + z) * K::EDGE + x
+    }
+    pub fn get(&self, x: usize, y: usize, z: usize) -> u32 {
+        self.get_index(Self::index(x, y, z))
+    }
+    pub fn get_index(&self, index: usize) -> u32 {
+        self.palette.id_of(self.storage.get(index))
+    }
+    // returns the previous value.
+    pub fn set(&mut self, x: usize, y: usize, z: usize, value: u32) -> u32 {
+        self.set_index(Self::index(x, y, z), value)
+    }
+    pub fn set_index(&mut self, index: usize, value: u32) -> u32 {
+        Self::check_id(value);
+        let previous = self.get_index(index);
+
+        let palette_index = self
+            .palette
+            .index_of(value)
+            .or_else(|| self.palette.try_insert(value, self.storage.bits));
+        match palette_index {
+            Some(palette_index) => self.storage.set(index, palette_index),
+            None => {
+                // the palette is full, so everything is packed again with more bits.
+                let mut values = self.values();
+                values[index] = value;
+                *self = Self::from_values(&values);
+            }
+        }
+
+        previous
+    }
+    pub fn values(&self) -> Vec<u32> {
+        (0..K::ENTRIES).map(|index| self.get_index(index)).collect()
+    }
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+    pub fn bits_per_entry(&self) -> u8 {
+        self.storage.bits
+    }
+
+    fn bits_for(distinct: usize) -> u8 {
+        if distinct <= 1 {
+            return 0;
+        }
+        let bits = (usize::BITS - (distinct - 1).leading_zeros()) as u8;
+        if bits > K::MAX_INDIRECT_BITS {
+            K::GLOBAL_BITS
+        } else {
+            bits.max(K::MIN_INDIRECT_BITS)
+        }
+    }
+    fn check_id(id: u32) {
+        assert!(
+            id < 1 << K::GLOBAL_BITS,
+            "id {} does not fit in {} bits",
+            id,
+            K::GLOBAL_BITS
+        );
+    }
+    fn palette_ids(&self) -> Vec<VarInt> {
+        match &self.palette {
+            Palette::Single(value) => vec![VarInt(*value as i32)],
+            Palette::Linear(ids) | Palette::HashMap(ids, _) => {
+                ids.iter().map(|&id| VarInt(id as i32)).collect()
+            }
+            Palette::Global => vec![],
+        }
+    }
+}
+
+impl<K: PaletteKind> Encodable for PalettedContainer<K> {
+    fn encode<T: Write>(&self, writer: &mut T) -> Result<usize> {
+        let mut written = self.storage.bits.encode(writer)?;
+        written += match &self.palette {
+            Palette::Single(value) => VarInt(*value as i32).encode(writer)?,
+            Palette::Linear(_) | Palette::HashMap(..) => {
+                Array::<VarIntLength, VarInt>::encode_slice(&self.palette_ids(), writer)?
+            }
+            Palette::Global => 0,
+        };
+        let data: Vec<i64> = self.storage.data.iter().map(|&long| long as i64).collect();
+        written += Array::<VarIntLength, i64>::encode_slice(&data, writer)?;
+
+        Ok(written)
+    }
+    fn encoded_len(&self) -> usize {
+        let palette = match &self.palette {
+            Palette::Single(value) => VarInt(*value as i32).encoded_len(),
+            Palette::Linear(_) | Palette::HashMap(..) => {
+                Array::<VarIntLength, VarInt>::encoded_len_slice(&self.palette_ids())
+            }
+            Palette::Global => 0,
+        };
+        let data = self.storage.data.len();
+
+        1 + palette + VarInt(data as i32).encoded_len() + data * 8
+    }
+}
+
+impl<K: PaletteKind> Decodable for PalettedContainer<K> {
+    fn decode<T: Read>(reader: &mut T) -> Result<Self> {
+        let sent_bits = u8::decode(reader).context("Failed to decode bits per entry")?;
+
+        let (palette, bits) = match sent_bits {
+            0 => {
+                let value =
+                    VarInt::decode(reader).context("Failed to decode single value palette")?;
+                (Palette::Single(Self::decode_id(value)?), 0)
+            }
+            bits if bits <= K::MAX_INDIRECT_BITS => {
+                let bits = bits.max(K::MIN_INDIRECT_BITS);
+                let ids = Array::<VarIntLength, VarInt>::decode_vec_bounded(reader, 1 << bits)
+                    .context("Failed to decode palette")?
+                    .into_iter()
+                    .map(Self::decode_id)
+                    .collect::<Result<Vec<_>>>()?;
+                ensure!(!ids.is_empty(), "Palette is empty");
+                (Palette::indirect(ids, bits), bits)
+            }
+            _ => (Palette::Global, K::GLOBAL_BITS),
+        };
+
+        let expected = PackedArray::long_count(bits, K::ENTRIES);
+        let data = Array::<VarIntLength, i64>::decode_vec_bounded(reader, expected.max(1))
+            .context("Failed to decode packed data")?;
+        // a single value palette may come with or without its (ignored) data.
+        if bits == 0 {
+            return Ok(Self::new(palette.id_of(0)));
+        }
+        ensure!(
+            data.len() == expected,
+            "Packed data length mismatch: expected {}, but got {}",
+            expected,
+            data.len()
+        );
+        let storage = PackedArray {
+            bits,
+            data: data.into_iter().map(|long| long as u64).collect(),
+        };
+
+        if let Palette::Linear(ids) | Palette::HashMap(ids, _) = &palette {
+            for index in 0..K::ENTRIES {
+                let palette_index = storage.get(index);
+                if palette_index as usize >= ids.len() {
+                    bail!(
+                        "Palette index out of range at {}: {} (palette of {})",
+                        index,
+                        palette_index,
+                        ids.len()
+                    );
+                }
+            }
+        }
+
+        Ok(Self {
+            palette,
+            storage,
+            _kind: PhantomData,
+        })
+    }
+}
+
+impl<K: PaletteKind> PalettedContainer<K> {
+    fn decode_id(id: VarInt) -> Result<u32> {
+        ensure!(
+            (0..1 << K::GLOBAL_BITS).contains(&id.0),
+            "Invalid palette id: {}",
+            id.0
+        );
+        Ok(id.0 as u32)
+    }
+}
+
+impl PalettedContainer<BlockStates> {
+    // what `ChunkSection::block_count` has to be. there is more than one air state (air, cave air and
+    // void air), and their ids change between versions, so the caller tells which ones are air.
+    pub fn non_air_count(&self, is_air: impl Fn(u32) -> bool) -> usize {
+        match &self.palette {
+            Palette::Single(id) if is_air(*id) => 0,
+            Palette::Single(_) => BlockStates::ENTRIES,
+            _ => (0..BlockStates::ENTRIES)
+                .filter(|&index| !is_air(self.get_index(index)))
+                .count(),
+        }
+    }
+}
+
+impl<K: PaletteKind> Default for PalettedContainer<K> {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip<K: PaletteKind + PartialEq + std::fmt::Debug>(
+        container: &PalettedContainer<K>,
+    ) -> Vec<u8> {
+        let mut buf = Vec::new();
+        let written = container.encode(&mut buf).unwrap();
+        assert_eq!(written, buf.len());
+        assert_eq!(container.encoded_len(), buf.len());
+
+        let decoded = PalettedContainer::<K>::decode(&mut buf.as_slice()).unwrap();
+        assert_eq!(&decoded, container);
+        buf
+    }
+
+    #[test]
+    fn single_value() {
+        let container = PalettedContainer::<BlockStates>::new(9);
+        assert_eq!(container.get(15, 15, 15), 9);
+        assert_eq!(round_trip(&container), vec![0, 9, 0]);
+    }
+
+    #[test]
+    fn grows_through_palettes() {
+        let mut container = PalettedContainer::<BlockStates>::new(0);
+
+        assert_eq!(container.set(1, 0, 0, 5), 0);
+        assert_eq!(container.bits_per_entry(), 4);
+        assert!(matches!(container.palette(), Palette::Linear(ids) if ids == &[0, 5]));
+        assert_eq!(container.get(1, 0, 0), 5);
+        assert_eq!(container.get(0, 0, 0), 0);
+
+        for id in 0..17 {
+            container.set_index(id as usize, id);
+        }
+        assert_eq!(container.bits_per_entry(), 5);
+        assert!(matches!(container.palette(), Palette::HashMap(..)));
+
+        for id in 0..300 {
+            container.set_index(id as usize, id * 100);
+        }
+        assert_eq!(container.bits_per_entry(), 15);
+        assert_eq!(container.palette(), &Palette::Global);
+
+        for id in 0..300 {
+            assert_eq!(container.get_index(id as usize), id * 100);
+        }
+        assert_eq!(container.get(15, 15, 15), 0);
+        round_trip(&container);
+    }
+
+    #[test]
+    fn biomes() {
+        let mut container = PalettedContainer::<Biomes>::new(1);
+        container.set(3, 3, 3, 2);
+        assert_eq!(container.bits_per_entry(), 1);
+        assert_eq!(PalettedContainer::<Biomes>::index(3, 3, 3), 63);
+
+        // 1 bit for 64 entries is a single long, and the last entry is its highest bit.
+        let buf = round_trip(&container);
+        assert_eq!(buf[..5], [1, 2, 1, 2, 1]);
+        assert_eq!(buf[5..], [0x80, 0, 0, 0, 0, 0, 0, 0]);
+
+        for id in 0..9 {
+            container.set_index(id, id as u32);
+        }
+        assert_eq!(container.bits_per_entry(), 6);
+        round_trip(&container);
+    }
+
+    #[test]
+    fn packing_does_not_span_longs() {
+        // 5 bits fit 12 values in a long, leaving the top 4 bits unused.
+        let values: Vec<u32> = (0..4096).map(|i| i % 20).collect();
+        let container = PalettedContainer::<BlockStates>::from_values(&values);
+        assert_eq!(container.bits_per_entry(), 5);
+        assert_eq!(container.storage.data.len(), 342);
+        assert_eq!(container.storage.data[0] >> 60, 0);
+        assert_eq!(container.storage.data[1] & 0x1f, 12);
+        assert_eq!(container.values(), values);
+    }
+
+    #[test]
+    fn decode_invalid() {
+        // 4 bits need 256 longs.
+        let mut buf = vec![4, 1, 0, 1];
+        buf.extend_from_slice(&[0; 8]);
+        PalettedContainer::<BlockStates>::decode(&mut buf.as_slice()).unwrap_err();
+
+        // index 1 of a palette of 1.
+        let mut buf = vec![4, 1, 0, 0x80, 0x02];
+        buf.extend_from_slice(&[0; 256 * 8]);
+        buf[5 + 7] = 1;
+        let e = PalettedContainer::<BlockStates>::decode(&mut buf.as_slice()).unwrap_err();
+        assert!(e.to_string().starts_with("Palette index out of range at 0"));
+
+        // below 4 bits is read as 4 bits.
+        buf[0] = 2;
+        buf[5 + 7] = 0;
+        let container = PalettedContainer::<BlockStates>::decode(&mut buf.as_slice()).unwrap();
+        assert_eq!(container.bits_per_entry(), 4);
+    }
+
+    #[test]
+    fn non_air_count() {
+        let is_air = |id| id == 0 || id == 7;
+        assert_eq!(
+            PalettedContainer::<BlockStates>::new(0).non_air_count(is_air),
+            0
+        );
+        assert_eq!(
+            PalettedContainer::<BlockStates>::new(1).non_air_count(is_air),
+            4096
+        );
+
+        let mut container = PalettedContainer::<BlockStates>::new(0);
+        container.set(0, 0, 0, 1);
+        container.set(1, 0, 0, 7);
+        container.set(2, 0, 0, 2);
+        assert_eq!(container.non_air_count(is_air), 2);
+    }
+}
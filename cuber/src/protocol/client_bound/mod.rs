@@ -1,66 +1,97 @@
-use std::io::BufWriter;
+use std::io::Write;
 
+use bytes::Bytes;
 use deriver::Encodable;
-use nbt::Blob;
 use packet_id::cb_packet;
 use structstruck;
 use uuid::Uuid;
 
+use anyhow::{Context as _, Result};
+
 use super::{
+    chunk::{Biomes, BlockStates, PalettedContainer},
     common::{
-        Difficulty, Feature, GameMode, InChunkOffset, PlayerAbilitiesFlags, SkyLightArray,
-        SynchronizePlayerPositionFlags,
+        ConnectionState, Difficulty, Feature, GameMode, InChunkOffset, PlayerAbilitiesFlags,
+        SkyLightArray, SynchronizePlayerPositionFlags,
     },
     primitive::{
-        array::{Array, PacketInferredInBytes, VarIntLength, VarIntLengthInBytes},
-        Angle, BoolConditional, Chat, Identifier, Position, Todo, VarInt,
+        array::{Array, ByteArray, VarIntLength, VarIntLengthInBytes},
+        bitset::BitSet,
+        Angle, BoolConditional, Chat, Identifier, NetworkNbt, Position, VarInt,
     },
-    BuiltPacket, Encodable, State,
+    server_bound,
+    status::ServerStatus,
+    version::ProtocolVersion,
+    BuiltPacket, Encodable,
 };
 
 pub trait ClientBoundPacket: Encodable {
-    const PACKET_ID: i32;
-    const VALID_STATE: State;
+    type ValidState: ConnectionState;
 
-    fn verify(&self, current_state: State) {
-        assert_eq!(current_state, Self::VALID_STATE);
-    }
-    fn to_bytes(&self) -> Box<[u8]> {
-        let mut buf: BufWriter<Vec<u8>> = BufWriter::new(Vec::new());
-        VarInt(Self::PACKET_ID).encode(&mut buf);
-        self.encode(&mut buf);
+    // `None` if the packet does not exist in the version.
+    fn packet_id(version: ProtocolVersion) -> Option<i32>;
+
+    // built for `ProtocolVersion::current()`.
+    fn to_bytes(&self) -> Result<Box<[u8]>> {
+        let version = ProtocolVersion::current();
+        let id = Self::packet_id(version).with_context(|| {
+            format!(
+                "{} does not exist in protocol version {}",
+                std::any::type_name::<Self>(),
+                version.0
+            )
+        })?;
+
+        let mut buf = Vec::with_capacity(VarInt(id).encoded_len() + self.encoded_len());
+        VarInt(id).encode(&mut buf)?;
+        self.encode(&mut buf)?;
 
-        buf.into_inner().unwrap().into_boxed_slice()
+        Ok(buf.into_boxed_slice())
     }
-    fn to_packet(&self) -> BuiltPacket {
-        BuiltPacket {
-            buf: self.to_bytes(),
-        }
+    fn to_packet(&self) -> Result<BuiltPacket> {
+        Ok(BuiltPacket {
+            buf: self.to_bytes()?,
+        })
     }
 }
 
-#[cb_packet(State::Status, 0)]
+#[cb_packet(State::Status, "minecraft:status_response")]
 #[derive(Encodable, Debug, PartialEq, Eq, Clone)]
 pub struct StatusResponse {
-    pub json_response: String, // replace with Json object.
+    pub json_response: ServerStatus,
 }
 
-#[cb_packet(State::Login, 0)]
+#[cb_packet(State::Status, "minecraft:pong_response")]
 #[derive(Encodable, Debug, PartialEq, Eq, Clone)]
+pub struct PingResponse {
+    pub payload: i64,
+}
+
+#[cb_packet(State::Login, "minecraft:login_disconnect")]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Disconnect {
     pub chat: Chat,
 }
+// unlike other chat fields, the login disconnect stays JSON after 1.20.3.
+impl Encodable for Disconnect {
+    fn encode<T: Write>(&self, writer: &mut T) -> Result<usize> {
+        self.chat.encode_json(writer)
+    }
+    fn encoded_len(&self) -> usize {
+        self.chat.encoded_json_len()
+    }
+}
 
-#[cb_packet(State::Login, 1)]
+#[cb_packet(State::Login, "minecraft:hello")]
 #[derive(Encodable, Debug, PartialEq, Eq, Clone)]
 pub struct EncryptionRequest {
     pub server_id: String,
-    pub public_key: Array<VarIntLength, u8>,
-    pub verify_token: Array<VarIntLength, u8>,
+    pub public_key: ByteArray<VarIntLength>,
+    pub verify_token: ByteArray<VarIntLength>,
 }
 
 structstruck::strike! {
-    #[cb_packet(State::Login, 0x02)]
+    #[cb_packet(State::Login, "minecraft:game_profile")]
     #[derive(Encodable, Debug, PartialEq, Eq, Clone)]
     pub struct LoginSuccess {
         pub uuid: Uuid,
@@ -73,21 +104,50 @@ structstruck::strike! {
     }
 }
 
-#[cb_packet(State::Login, 0x03)]
+#[cb_packet(State::Login, "minecraft:login_compression")]
 #[derive(Encodable, Debug, PartialEq, Eq, Clone)]
 pub struct SetCompression {
     pub threshold: VarInt,
 }
 
-#[cb_packet(State::Login, 0x04)]
+#[cb_packet(State::Login, "minecraft:custom_query")]
 #[derive(Encodable, Debug, PartialEq, Eq, Clone)]
 pub struct PluginRequest {
     pub message_id: VarInt,
     pub channel: Identifier,
-    pub data: Array<PacketInferredInBytes, u8>,
+    pub data: Bytes,
+}
+
+#[cb_packet(State::Configuration, "minecraft:custom_payload", since = V1_20_2)]
+#[derive(Encodable, Debug, PartialEq, Eq, Clone)]
+pub struct ConfigPluginMessage {
+    pub channel: Identifier,
+    pub data: Bytes,
+}
+
+#[cb_packet(State::Configuration, "minecraft:disconnect", since = V1_20_2)]
+#[derive(Encodable, Debug, PartialEq, Eq, Clone)]
+pub struct ConfigDisconnect {
+    pub chat: Chat,
+}
+
+#[cb_packet(State::Configuration, "minecraft:finish_configuration", since = V1_20_2)]
+#[derive(Encodable, Debug, PartialEq, Eq, Clone)]
+pub struct FinishConfiguration {}
+
+#[cb_packet(State::Configuration, "minecraft:registry_data", since = V1_20_2)]
+#[derive(Encodable, Debug, PartialEq, Clone)]
+pub struct RegistryData {
+    pub registry_codec: NetworkNbt,
+}
+
+#[cb_packet(State::Configuration, "minecraft:update_enabled_features", since = V1_20_2)]
+#[derive(Encodable, Debug, PartialEq, Eq, Clone)]
+pub struct ConfigFeatureFlags {
+    pub features: Array<VarIntLength, Feature>,
 }
 
-#[cb_packet(State::Play, 0x01)]
+#[cb_packet(State::Play, "minecraft:add_entity")]
 #[derive(Encodable, Debug, PartialEq, Clone, Copy)]
 pub struct SpawnEntity {
     pub entity_id: VarInt,
@@ -105,61 +165,116 @@ pub struct SpawnEntity {
     pub velocity_z: i16,
 }
 
-#[cb_packet(State::Play, 0x0C)]
+#[cb_packet(State::Play, "minecraft:change_difficulty")]
 #[derive(Encodable, Debug, PartialEq, Eq, Clone)]
 pub struct ChangeDifficulty {
     pub new_difficulty: Difficulty,
     pub difficulty_locked: bool,
 }
 
-#[cb_packet(State::Play, 0x17)]
+#[cb_packet(State::Play, "minecraft:custom_payload")]
 #[derive(Encodable, Debug, PartialEq, Eq, Clone)]
 pub struct PluginMessage {
     pub channel: Identifier,
-    pub data: Array<PacketInferredInBytes, u8>,
+    pub data: Bytes,
 }
 
 structstruck::strike! {
-    #[cb_packet(State::Play, 0x24)]
+    #[cb_packet(State::Play, "minecraft:level_chunk_with_light")]
     #[derive(Encodable, Debug, PartialEq, Clone)]
     pub struct ChunkDataAndUpdateLight {
         pub chunk_x: i32,
         pub chunk_z: i32,
-        pub height_maps: nbt::Blob,
+        pub height_maps: NetworkNbt,
         pub chunk_data: Array<VarIntLengthInBytes, #[derive(Encodable, Debug, PartialEq, Clone)] pub struct ChunkSection {
-            pub block_count: i16,
-            pub block_states: #[derive(Encodable, Debug, PartialEq, Clone)] pub struct PalettedContainer {
-                pub bits_per_entry: u8,
-                pub palette: Todo,
-                pub data_array: Array<VarIntLength, i64>,
-            },
-            pub biomes: PalettedContainer,
+            pub block_count: i16, // non-air blocks, kept in sync by the caller or `ChunkSection::new`
+            pub block_states: PalettedContainer<BlockStates>,
+            pub biomes: PalettedContainer<Biomes>,
         }>,
         pub block_entities: Array<VarIntLength, #[derive(Encodable, Debug, PartialEq, Clone)] pub struct BlockEntity {
             pub xy: InChunkOffset,
             pub y: u16,
             pub be_type: VarInt,
-            pub data: nbt::Blob,
+            pub data: NetworkNbt,
         }>,
-        pub sky_light_mask: u32, // replace with BitSet
-        pub block_light_mask: u32,
-        pub empty_sky_light_mask: u32,
-        pub empty_block_light_mask: u32,
-        pub sky_lights_array: Array<VarIntLength, SkyLightArray>,
-        pub block_lights_array: Array<VarIntLength, SkyLightArray>,
+        pub light: #[derive(Encodable, Debug, Default, PartialEq, Eq, Clone)] pub struct LightData {
+            pub sky_light_mask: BitSet,
+            pub block_light_mask: BitSet,
+            pub empty_sky_light_mask: BitSet,
+            pub empty_block_light_mask: BitSet,
+            pub sky_lights_array: Array<VarIntLength, SkyLightArray>,
+            pub block_lights_array: Array<VarIntLength, SkyLightArray>,
+        },
+    }
+}
+
+impl ChunkSection {
+    // `is_air` picks the air states out of `block_states`, see `PalettedContainer::non_air_count`.
+    pub fn new(
+        block_states: PalettedContainer<BlockStates>,
+        biomes: PalettedContainer<Biomes>,
+        is_air: impl Fn(u32) -> bool,
+    ) -> Self {
+        Self {
+            block_count: block_states.non_air_count(is_air) as i16,
+            block_states,
+            biomes,
+        }
+    }
+}
+
+impl LightData {
+    // light sections start one below the lowest section of the world, and end one above the highest.
+    // `None` keeps what the client has, and an all dark section is only marked in the empty mask.
+    pub fn from_sections(
+        sky_lights: &[Option<SkyLightArray>],
+        block_lights: &[Option<SkyLightArray>],
+    ) -> Self {
+        let (sky_light_mask, empty_sky_light_mask, sky_lights_array) = Self::pack(sky_lights);
+        let (block_light_mask, empty_block_light_mask, block_lights_array) =
+            Self::pack(block_lights);
+
+        Self {
+            sky_light_mask,
+            block_light_mask,
+            empty_sky_light_mask,
+            empty_block_light_mask,
+            sky_lights_array,
+            block_lights_array,
+        }
+    }
+    fn pack(
+        sections: &[Option<SkyLightArray>],
+    ) -> (BitSet, BitSet, Array<VarIntLength, SkyLightArray>) {
+        let mut mask = BitSet::new();
+        let mut empty_mask = BitSet::new();
+        let mut arrays = Vec::new();
+
+        for (index, light) in sections.iter().enumerate() {
+            match light {
+                Some(light) if light.is_empty() => empty_mask.set(index, true),
+                Some(light) => {
+                    mask.set(index, true);
+                    arrays.push(light.clone());
+                }
+                None => {}
+            }
+        }
+
+        (mask, empty_mask, arrays.into())
     }
 }
 
 structstruck::strike! {
-    #[cb_packet(State::Play, 0x28)]
-    #[derive(Encodable, Debug, PartialEq, Clone)]
+    #[cb_packet(State::Play, "minecraft:login")]
+    #[derive(Debug, PartialEq, Clone)]
     pub struct LoginPlay {
         pub(crate) entity_id: i32, // TODO: replace with Entity structure
         pub(crate) is_hardcore: bool,
         pub(crate) game_mode: GameMode,
         pub(crate) previous_game_mode: GameMode,
         pub(crate) dimension_names: Array<VarIntLength, Identifier>,
-        pub(crate) registry_codec: Blob,
+        pub(crate) registry_codec: NetworkNbt,
         pub(crate) dimension_type: Identifier,
         pub(crate) dimension_name: Identifier,
         pub(crate) hashed_seed: u64,
@@ -168,17 +283,97 @@ structstruck::strike! {
         pub(crate) simulation_distance: VarInt,
         pub(crate) reduce_debug_info: bool,
         pub(crate) enable_respawn_screen: bool,
+        pub(crate) do_limited_crafting: bool, // since 1.20.2
         pub(crate) is_debug: bool,
         pub(crate) is_flat: bool,
         pub(crate) death_location: BoolConditional<#[derive(Encodable, Debug, PartialEq, Eq, Clone)] pub struct DeathLocation {
             pub(crate) dimension_name: Identifier,
             pub(crate) location: Position,
         }>,
-        pub(crate) portal_cooldown: VarInt,
+        pub(crate) portal_cooldown: VarInt, // since 1.20
     }
 }
 
-#[cb_packet(State::Play, 0x34)]
+// reordered in 1.20.2, when the registry codec moved to the configuration state.
+impl Encodable for LoginPlay {
+    fn encode<T: Write>(&self, writer: &mut T) -> Result<usize> {
+        let version = ProtocolVersion::current();
+
+        let mut written = 0;
+        written += self.entity_id.encode(writer)?;
+        written += self.is_hardcore.encode(writer)?;
+        if version < ProtocolVersion::V1_20_2 {
+            written += self.game_mode.encode(writer)?;
+            written += self.previous_game_mode.encode(writer)?;
+            written += self.dimension_names.encode(writer)?;
+            written += self
+                .registry_codec
+                .encode(writer)
+                .context("Failed to encode registry_codec")?;
+            written += self.dimension_type.encode(writer)?;
+            written += self.dimension_name.encode(writer)?;
+            written += self.hashed_seed.encode(writer)?;
+            written += self.max_players.encode(writer)?;
+            written += self.view_distance.encode(writer)?;
+            written += self.simulation_distance.encode(writer)?;
+            written += self.reduce_debug_info.encode(writer)?;
+            written += self.enable_respawn_screen.encode(writer)?;
+        } else {
+            written += self.dimension_names.encode(writer)?;
+            written += self.max_players.encode(writer)?;
+            written += self.view_distance.encode(writer)?;
+            written += self.simulation_distance.encode(writer)?;
+            written += self.reduce_debug_info.encode(writer)?;
+            written += self.enable_respawn_screen.encode(writer)?;
+            written += self.do_limited_crafting.encode(writer)?;
+            written += self.dimension_type.encode(writer)?;
+            written += self.dimension_name.encode(writer)?;
+            written += self.hashed_seed.encode(writer)?;
+            written += self.game_mode.encode(writer)?;
+            written += self.previous_game_mode.encode(writer)?;
+        }
+        written += self.is_debug.encode(writer)?;
+        written += self.is_flat.encode(writer)?;
+        written += self.death_location.encode(writer)?;
+        if version >= ProtocolVersion::V1_20_1 {
+            written += self.portal_cooldown.encode(writer)?;
+        }
+
+        Ok(written)
+    }
+    // both orders share every field but the registry codec (until 1.20.1) and do_limited_crafting (since 1.20.2).
+    fn encoded_len(&self) -> usize {
+        let version = ProtocolVersion::current();
+
+        let mut len = self.entity_id.encoded_len()
+            + self.is_hardcore.encoded_len()
+            + self.game_mode.encoded_len()
+            + self.previous_game_mode.encoded_len()
+            + self.dimension_names.encoded_len()
+            + self.dimension_type.encoded_len()
+            + self.dimension_name.encoded_len()
+            + self.hashed_seed.encoded_len()
+            + self.max_players.encoded_len()
+            + self.view_distance.encoded_len()
+            + self.simulation_distance.encoded_len()
+            + self.reduce_debug_info.encoded_len()
+            + self.enable_respawn_screen.encoded_len()
+            + self.is_debug.encoded_len()
+            + self.is_flat.encoded_len()
+            + self.death_location.encoded_len();
+        if version < ProtocolVersion::V1_20_2 {
+            len += self.registry_codec.encoded_len();
+        } else {
+            len += self.do_limited_crafting.encoded_len();
+        }
+        if version >= ProtocolVersion::V1_20_1 {
+            len += self.portal_cooldown.encoded_len();
+        }
+        len
+    }
+}
+
+#[cb_packet(State::Play, "minecraft:player_abilities")]
 #[derive(Encodable, Debug, PartialEq, Clone)]
 pub struct PlayerAbilities {
     pub flags: PlayerAbilitiesFlags,
@@ -187,7 +382,7 @@ pub struct PlayerAbilities {
 }
 
 structstruck::strike! {
-    #[cb_packet(State::Play, 0x3c)]
+    #[cb_packet(State::Play, "minecraft:player_position")]
     #[derive(Encodable, Debug, PartialEq, Clone)]
     pub struct SynchronizePlayerPosition {
         pub x: f64,
@@ -200,14 +395,88 @@ structstruck::strike! {
     }
 }
 
-#[cb_packet(State::Play, 0x4d)]
+#[cb_packet(State::Play, "minecraft:set_carried_item")]
 #[derive(Encodable, Debug, PartialEq, Clone)]
 pub struct SetHeldItem {
     pub slot: u8,
 }
 
-#[cb_packet(State::Play, 0x6b)]
+#[cb_packet(State::Play, "minecraft:update_enabled_features", removed = V1_20_2)]
 #[derive(Encodable, Debug, PartialEq, Eq, Clone)]
 pub struct FeatureFlags {
     pub features: Array<VarIntLength, Feature>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn light_data_from_sections() {
+        let mut lit = SkyLightArray::default();
+        lit.set(1, 2, 3, 15);
+
+        let sky = [
+            Some(SkyLightArray::default()),
+            Some(lit.clone()),
+            None,
+            Some(SkyLightArray::filled(15)),
+        ];
+        let light = LightData::from_sections(&sky, &[None, Some(lit.clone())]);
+
+        assert_eq!(light.sky_light_mask.ones().collect::<Vec<_>>(), vec![1, 3]);
+        assert_eq!(
+            light.empty_sky_light_mask.ones().collect::<Vec<_>>(),
+            vec![0]
+        );
+        assert_eq!(
+            light.sky_lights_array.inner,
+            vec![lit.clone(), SkyLightArray::filled(15)]
+        );
+        assert_eq!(light.block_light_mask.ones().collect::<Vec<_>>(), vec![1]);
+        assert!(light.empty_block_light_mask.is_empty());
+        assert_eq!(light.block_lights_array.inner, vec![lit]);
+
+        // 3 masks of a single long and an empty one, then 3 arrays of 2048 bytes with their lengths.
+        let mut buf = Vec::new();
+        light.encode(&mut buf).unwrap();
+        assert_eq!(buf.len(), 3 * 9 + 1 + 2 + 3 * (2 + 2048));
+        assert_eq!(light.encoded_len(), buf.len());
+        assert_eq!(buf[..9], [1, 0, 0, 0, 0, 0, 0, 0, 0b1010]);
+    }
+
+    #[test]
+    fn login_play_encoded_len() {
+        let mut codec = nbt::Blob::named("");
+        codec.insert("a", 1_i8).unwrap();
+        let login_play = LoginPlay {
+            entity_id: 1,
+            is_hardcore: false,
+            game_mode: GameMode::Creative,
+            previous_game_mode: GameMode::Undefined,
+            dimension_names: vec!["minecraft:overworld".into()].into(),
+            registry_codec: codec.into(),
+            dimension_type: "minecraft:overworld".into(),
+            dimension_name: "minecraft:overworld".into(),
+            hashed_seed: 0,
+            max_players: 20.into(),
+            view_distance: 10.into(),
+            simulation_distance: 10.into(),
+            reduce_debug_info: false,
+            enable_respawn_screen: true,
+            do_limited_crafting: false,
+            is_debug: false,
+            is_flat: false,
+            death_location: None.into(),
+            portal_cooldown: 10.into(),
+        };
+
+        for version in ProtocolVersion::SUPPORTED {
+            version.scope(|| {
+                let mut buf = Vec::new();
+                login_play.encode(&mut buf).unwrap();
+                assert_eq!(login_play.encoded_len(), buf.len(), "{:?}", version);
+            });
+        }
+    }
+}
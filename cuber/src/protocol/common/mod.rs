@@ -2,9 +2,10 @@ use std::hash::Hash;
 
 use super::{
     primitive::{
-        array::{Array, VarIntLengthInBytes},
+        array::{Array, VarIntLength},
         Identifier,
     },
+    server_bound::PacketCluster,
     Decodable, Encodable,
 };
 use deriver::{Decodable, Encodable};
@@ -16,9 +17,23 @@ pub enum State {
     Handshaking,
     Status,
     Login,
+    Configuration,
     Play,
 }
 
+// implemented by the server bound packet clusters, which double as the type level state of `Client`.
+pub trait ConnectionState: PacketCluster {
+    const STATE: State;
+}
+
+// what to do when a packet is not fully consumed by its definition.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum Strictness {
+    Strict,
+    Warn,
+    Ignore,
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
 pub enum Compression {
     Disabled,
@@ -33,96 +48,45 @@ pub enum Encryption {
     Enabled,
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+#[derive(Encodable, Decodable, Debug, PartialEq, Eq, Clone, Copy, Hash)]
+#[repr_kind(i8)]
 pub enum GameMode {
+    #[value(-1)]
     Undefined,
+    #[value = 0]
     Survival,
+    #[value = 1]
     Creative,
+    #[value = 2]
     Adventure,
+    #[value = 3]
     Spectator,
 }
 
-impl Decodable for GameMode {
-    fn decode<T: std::io::Read>(reader: &mut T) -> Result<Self> {
-        let raw = i8::decode(reader).context("Failed to decode game mode")?;
-        match raw {
-            -1 => Ok(GameMode::Undefined),
-            0 => Ok(GameMode::Survival),
-            1 => Ok(GameMode::Creative),
-            2 => Ok(GameMode::Adventure),
-            3 => Ok(GameMode::Spectator),
-            id => bail!("Invalid game mode: {}", id),
-        }
-    }
-}
-
-impl Encodable for GameMode {
-    fn encode<T: std::io::Write>(&self, writer: &mut T) -> usize {
-        let raw: i8 = match self {
-            GameMode::Undefined => -1,
-            GameMode::Survival => 0,
-            GameMode::Creative => 1,
-            GameMode::Adventure => 2,
-            GameMode::Spectator => 3,
-        };
-        raw.encode(writer)
-    }
-}
-
-#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+#[derive(Encodable, Decodable, Debug, PartialEq, Eq, Clone, Hash)]
+#[repr_kind(string)]
 pub enum Feature {
+    #[value = "minecraft:vanilla"]
     Vanilla,
+    #[value = "minecraft:bundle"]
     Bundle,
     Other(Identifier),
 }
 
-impl Encodable for Feature {
-    fn encode<T: std::io::Write>(&self, writer: &mut T) -> usize {
-        let ident = match self {
-            Self::Vanilla => "minecraft:vanilla".into(),
-            Self::Bundle => "minecraft:bundle".into(),
-            Self::Other(ident) => ident.clone(),
-        };
-
-        ident.encode(writer)
-    }
-}
-
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+#[derive(Encodable, Decodable, Debug, PartialEq, Eq, Clone, Copy, Hash)]
+#[repr_kind(u8)]
 pub enum Difficulty {
+    #[value = 0]
     Peaceful,
+    #[value = 1]
     Easy,
+    #[value = 2]
     Normal,
+    #[value = 3]
     Hard,
     Other(u8),
 }
 
-impl Decodable for Difficulty {
-    fn decode<T: std::io::Read>(reader: &mut T) -> Result<Self> {
-        let raw = u8::decode(reader).context("Failed to decode difficulty")?;
-        match raw {
-            0 => Ok(Difficulty::Peaceful),
-            1 => Ok(Difficulty::Easy),
-            2 => Ok(Difficulty::Normal),
-            3 => Ok(Difficulty::Hard),
-            id => Ok(Difficulty::Other(id)),
-        }
-    }
-}
-
-impl Encodable for Difficulty {
-    fn encode<T: std::io::Write>(&self, writer: &mut T) -> usize {
-        let raw: u8 = match self {
-            Difficulty::Peaceful => 0,
-            Difficulty::Easy => 1,
-            Difficulty::Normal => 2,
-            Difficulty::Hard => 3,
-            Difficulty::Other(id) => *id,
-        };
-        raw.encode(writer)
-    }
-}
-
 bitflags::bitflags! {
     #[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
     pub struct PlayerAbilitiesFlags: u8 {
@@ -134,9 +98,12 @@ bitflags::bitflags! {
 }
 
 impl Encodable for PlayerAbilitiesFlags {
-    fn encode<T: std::io::Write>(&self, writer: &mut T) -> usize {
+    fn encode<T: std::io::Write>(&self, writer: &mut T) -> Result<usize> {
         self.bits().encode(writer)
     }
+    fn encoded_len(&self) -> usize {
+        1
+    }
 }
 
 impl Decodable for PlayerAbilitiesFlags {
@@ -161,14 +128,18 @@ bitflags::bitflags! {
 }
 
 impl Encodable for SynchronizePlayerPositionFlags {
-    fn encode<T: std::io::Write>(&self, writer: &mut T) -> usize {
+    fn encode<T: std::io::Write>(&self, writer: &mut T) -> Result<usize> {
         self.bits().encode(writer)
     }
+    fn encoded_len(&self) -> usize {
+        1
+    }
 }
 
 impl Decodable for SynchronizePlayerPositionFlags {
     fn decode<T: std::io::Read>(reader: &mut T) -> Result<Self> {
-        let raw = u8::decode(reader).context("Failed to decode synchronize player position flags")?;
+        let raw =
+            u8::decode(reader).context("Failed to decode synchronize player position flags")?;
         match Self::from_bits(raw) {
             Some(flags) => Ok(flags),
             None => bail!("Invalid synchronize player position flags: {}", raw),
@@ -204,9 +175,12 @@ impl Hash for InChunkOffset {
 }
 
 impl Encodable for InChunkOffset {
-    fn encode<T: std::io::Write>(&self, writer: &mut T) -> usize {
+    fn encode<T: std::io::Write>(&self, writer: &mut T) -> Result<usize> {
         self.pack().encode(writer)
     }
+    fn encoded_len(&self) -> usize {
+        1
+    }
 }
 
 impl Decodable for InChunkOffset {
@@ -221,12 +195,22 @@ impl Decodable for InChunkOffset {
     }
 }
 
-#[derive(Decodable, Encodable, Debug, PartialEq, Eq, Clone, Hash)]
+// light levels of a section, 4 bits per block. used for block light as well.
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
 pub struct SkyLightArray {
-    array: Array<VarIntLengthInBytes, u8>,
+    array: Box<[u8; LIGHT_ARRAY_LEN]>,
 }
 
+pub const LIGHT_ARRAY_LEN: usize = 2048;
+
 impl SkyLightArray {
+    // every block has the light level of `level`.
+    pub fn filled(level: u8) -> Self {
+        assert!(level < 16);
+        Self {
+            array: Box::new([level << 4 | level; LIGHT_ARRAY_LEN]),
+        }
+    }
     pub fn to_index(x: u32, y: u32, z: u32) -> (usize, bool) {
         assert!(x < 16);
         assert!(y < 16);
@@ -239,11 +223,94 @@ impl SkyLightArray {
     }
     pub fn get(&self, x: u32, y: u32, z: u32) -> u8 {
         let (index, is_upper) = Self::to_index(x, y, z);
-        let byte = self.array.inner[index];
+        let byte = self.array[index];
         if is_upper {
             byte >> 4
         } else {
             byte & 0x0f
         }
     }
+    pub fn set(&mut self, x: u32, y: u32, z: u32, level: u8) {
+        assert!(level < 16);
+        let (index, is_upper) = Self::to_index(x, y, z);
+        let byte = &mut self.array[index];
+        if is_upper {
+            *byte = (*byte & 0x0f) | level << 4;
+        } else {
+            *byte = (*byte & 0xf0) | level;
+        }
+    }
+    // all dark, which is sent as a bit of the empty mask instead of an array.
+    pub fn is_empty(&self) -> bool {
+        self.array.iter().all(|&byte| byte == 0)
+    }
+    pub fn as_bytes(&self) -> &[u8; LIGHT_ARRAY_LEN] {
+        &self.array
+    }
+}
+
+impl Default for SkyLightArray {
+    fn default() -> Self {
+        Self::filled(0)
+    }
+}
+
+impl Encodable for SkyLightArray {
+    fn encode<T: std::io::Write>(&self, writer: &mut T) -> Result<usize> {
+        Array::<VarIntLength, u8>::encode_slice(&self.array[..], writer)
+    }
+    fn encoded_len(&self) -> usize {
+        Array::<VarIntLength, u8>::encoded_len_slice(&self.array[..])
+    }
+}
+
+impl Decodable for SkyLightArray {
+    fn decode<T: std::io::Read>(reader: &mut T) -> Result<Self> {
+        let array = Array::<VarIntLength, u8>::decode_vec_bounded(reader, LIGHT_ARRAY_LEN)
+            .context("Failed to decode light array")?;
+        let len = array.len();
+        match array.into_boxed_slice().try_into() {
+            Ok(array) => Ok(Self { array }),
+            Err(_) => bail!(
+                "Invalid light array length: expected {}, but got {}",
+                LIGHT_ARRAY_LEN,
+                len
+            ),
+        }
+    }
+}
+
+impl From<[u8; LIGHT_ARRAY_LEN]> for SkyLightArray {
+    fn from(array: [u8; LIGHT_ARRAY_LEN]) -> Self {
+        Self {
+            array: Box::new(array),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sky_light_array() {
+        let mut light = SkyLightArray::default();
+        assert!(light.is_empty());
+        light.set(1, 0, 0, 7);
+        light.set(0, 0, 0, 3);
+        assert_eq!(light.get(1, 0, 0), 7);
+        assert_eq!(light.as_bytes()[0], 0x73);
+        assert!(!light.is_empty());
+
+        let mut buf = Vec::new();
+        light.encode(&mut buf).unwrap();
+        assert_eq!(buf[..3], [0x80, 0x10, 0x73]);
+        assert_eq!(SkyLightArray::decode(&mut buf.as_slice()).unwrap(), light);
+
+        // exactly 2048 bytes.
+        let mut short = vec![0xff, 0x0f];
+        short.extend_from_slice(&[0; 2047]);
+        SkyLightArray::decode(&mut short.as_slice()).unwrap_err();
+        SkyLightArray::decode(&mut [0x81, 0x10].as_slice()).unwrap_err();
+    }
 }
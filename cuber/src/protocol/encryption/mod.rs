@@ -0,0 +1,283 @@
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use aes::Aes128;
+use cfb8::cipher::{generic_array::GenericArray, BlockDecryptMut, BlockEncryptMut, KeyIvInit};
+use rsa::{pkcs8::EncodePublicKey, Pkcs1v15Encrypt, RsaPrivateKey};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use anyhow::{ensure, Context as _, Result};
+
+pub type SharedSecret = [u8; 16];
+
+type Encryptor = cfb8::Encryptor<Aes128>;
+type Decryptor = cfb8::Decryptor<Aes128>;
+
+pub struct ServerKey {
+    private_key: RsaPrivateKey,
+    public_key_der: Box<[u8]>,
+}
+
+impl ServerKey {
+    pub fn generate() -> Result<Self> {
+        let private_key = RsaPrivateKey::new(&mut rand::thread_rng(), 1024)
+            .context("Failed to generate RSA key pair")?;
+        let public_key_der = private_key
+            .to_public_key()
+            .to_public_key_der()
+            .context("Failed to encode public key")?
+            .into_vec()
+            .into_boxed_slice();
+
+        Ok(Self {
+            private_key,
+            public_key_der,
+        })
+    }
+    pub fn public_key_der(&self) -> &[u8] {
+        &self.public_key_der
+    }
+    pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
+        self.private_key
+            .decrypt(Pkcs1v15Encrypt, data)
+            .context("Failed to decrypt with server key")
+    }
+    pub fn decrypt_shared_secret(&self, data: &[u8]) -> Result<SharedSecret> {
+        let secret = self.decrypt(data)?;
+        ensure!(
+            secret.len() == 16,
+            "Invalid shared secret length: {}",
+            secret.len()
+        );
+
+        let mut shared_secret = [0; 16];
+        shared_secret.copy_from_slice(&secret);
+        Ok(shared_secret)
+    }
+}
+
+impl std::fmt::Debug for ServerKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ServerKey")
+            .field("public_key_der", &self.public_key_der)
+            .finish_non_exhaustive()
+    }
+}
+
+pub fn generate_verify_token() -> [u8; 4] {
+    rand::random()
+}
+
+pub struct CipherReader<R> {
+    inner: R,
+    cipher: Option<Decryptor>,
+}
+
+impl<R> CipherReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            cipher: None,
+        }
+    }
+    pub fn enable(&mut self, shared_secret: &SharedSecret) {
+        assert!(self.cipher.is_none(), "cipher is already enabled");
+        self.cipher = Some(Decryptor::new(shared_secret.into(), shared_secret.into()));
+    }
+    pub fn is_enabled(&self) -> bool {
+        self.cipher.is_some()
+    }
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for CipherReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let filled = buf.filled().len();
+
+        let result = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let (Poll::Ready(Ok(())), Some(cipher)) = (&result, &mut this.cipher) {
+            for byte in &mut buf.filled_mut()[filled..] {
+                cipher.decrypt_block_mut(GenericArray::from_mut_slice(std::slice::from_mut(byte)));
+            }
+        }
+
+        result
+    }
+}
+
+impl<R: std::fmt::Debug> std::fmt::Debug for CipherReader<R> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CipherReader")
+            .field("inner", &self.inner)
+            .field("enabled", &self.is_enabled())
+            .finish()
+    }
+}
+
+pub struct CipherWriter<W> {
+    inner: W,
+    cipher: Option<Encryptor>,
+    pending: Vec<u8>, // encrypted, but not yet written to inner.
+}
+
+impl<W> CipherWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            cipher: None,
+            pending: Vec::new(),
+        }
+    }
+    pub fn enable(&mut self, shared_secret: &SharedSecret) {
+        assert!(self.cipher.is_none(), "cipher is already enabled");
+        self.cipher = Some(Encryptor::new(shared_secret.into(), shared_secret.into()));
+    }
+    pub fn is_enabled(&self) -> bool {
+        self.cipher.is_some()
+    }
+}
+
+impl<W: AsyncWrite + Unpin> CipherWriter<W> {
+    fn poll_write_pending(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !self.pending.is_empty() {
+            match Pin::new(&mut self.inner).poll_write(cx, &self.pending) {
+                Poll::Ready(Ok(0)) => {
+                    return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+                }
+                Poll::Ready(Ok(written)) => {
+                    self.pending.drain(..written);
+                }
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for CipherWriter<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        if this.cipher.is_none() {
+            return Pin::new(&mut this.inner).poll_write(cx, buf);
+        }
+
+        if this.poll_write_pending(cx)?.is_pending() {
+            return Poll::Pending;
+        }
+
+        let cipher = this.cipher.as_mut().unwrap();
+        let mut encrypted = buf.to_vec();
+        for byte in &mut encrypted {
+            cipher.encrypt_block_mut(GenericArray::from_mut_slice(std::slice::from_mut(byte)));
+        }
+        this.pending = encrypted;
+
+        // the cipher state has already advanced, so whatever is left is written on the next poll.
+        let _ = this.poll_write_pending(cx)?;
+
+        Poll::Ready(Ok(buf.len()))
+    }
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.poll_write_pending(cx)?.is_pending() {
+            return Poll::Pending;
+        }
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.poll_write_pending(cx)?.is_pending() {
+            return Poll::Pending;
+        }
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+impl<W: std::fmt::Debug> std::fmt::Debug for CipherWriter<W> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CipherWriter")
+            .field("inner", &self.inner)
+            .field("enabled", &self.is_enabled())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    const SECRET: SharedSecret = [
+        0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee,
+        0xff,
+    ];
+
+    #[tokio::test]
+    async fn cipher_round_trip() {
+        let mut writer = CipherWriter::new(Vec::new());
+        writer.write_all(b"plain").await.unwrap();
+        writer.enable(&SECRET);
+        writer.write_all(b"hello, ").await.unwrap();
+        writer.write_all(b"world").await.unwrap();
+        writer.flush().await.unwrap();
+
+        let written = writer.inner;
+        assert_eq!(&written[..5], b"plain");
+        assert_ne!(&written[5..], b"hello, world");
+
+        let mut reader = CipherReader::new(&written[..]);
+        let mut plain = [0; 5];
+        reader.read_exact(&mut plain).await.unwrap();
+        reader.enable(&SECRET);
+
+        let mut decrypted = Vec::new();
+        reader.read_to_end(&mut decrypted).await.unwrap();
+
+        assert_eq!(&plain, b"plain");
+        assert_eq!(decrypted, b"hello, world");
+    }
+
+    #[tokio::test]
+    async fn cipher_is_stream() {
+        let mut at_once = CipherWriter::new(Vec::new());
+        at_once.enable(&SECRET);
+        at_once.write_all(b"minecraft").await.unwrap();
+
+        let mut split = CipherWriter::new(Vec::new());
+        split.enable(&SECRET);
+        for byte in b"minecraft" {
+            split.write_all(&[*byte]).await.unwrap();
+        }
+
+        assert_eq!(at_once.inner, split.inner);
+    }
+
+    #[test]
+    fn server_key_decrypt() {
+        use rsa::{pkcs8::DecodePublicKey, RsaPublicKey};
+
+        let key = ServerKey::generate().unwrap();
+        let public_key = RsaPublicKey::from_public_key_der(key.public_key_der()).unwrap();
+        let encrypted = public_key
+            .encrypt(&mut rand::thread_rng(), Pkcs1v15Encrypt, &SECRET)
+            .unwrap();
+
+        assert_eq!(key.decrypt_shared_secret(&encrypted).unwrap(), SECRET);
+    }
+}
@@ -0,0 +1,305 @@
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use super::{chat::TextComponent, status::ServerStatus};
+
+use anyhow::{bail, ensure, Context as _, Result};
+
+pub const LEGACY_PING_ID: u8 = 0xfe;
+pub const LEGACY_KICK_ID: u8 = 0xff;
+
+// pre-netty clients compare this with their own protocol number (78 at most), not with a netty one.
+// vanilla servers since 1.7 answer with 127, which no legacy client has.
+pub const LEGACY_PROTOCOL_VERSION: i32 = 127;
+
+const PING_PAYLOAD: u8 = 0x01;
+const PLUGIN_MESSAGE_ID: u8 = 0xfa;
+const PING_HOST_CHANNEL: &str = "MC|PingHost";
+
+// beta clients send the bare id and wait, so a missing payload is told apart by a timeout.
+const PAYLOAD_TIMEOUT: Duration = Duration::from_millis(100);
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum LegacyPing {
+    // beta 1.8 - 1.3: `FE`
+    Beta,
+    // 1.4 - 1.5: `FE 01`
+    V1_4,
+    // 1.6: `FE 01 FA` followed by a `MC|PingHost` plugin message
+    V1_6 {
+        protocol_version: u8,
+        host: String,
+        port: i32,
+    },
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct LegacyStatus {
+    pub protocol_version: i32,
+    pub version_name: String,
+    pub motd: String,
+    pub online: i32,
+    pub max: i32,
+}
+
+impl From<&ServerStatus> for LegacyStatus {
+    fn from(status: &ServerStatus) -> Self {
+        let (online, max) = status
+            .players
+            .as_ref()
+            .map_or((0, 0), |players| (players.online, players.max));
+
+        Self {
+            protocol_version: LEGACY_PROTOCOL_VERSION,
+            version_name: status.version.name.clone(),
+            motd: status
+                .description
+                .as_ref()
+                .map(|description| description.component.to_legacy())
+                .unwrap_or_default(),
+            online,
+            max,
+        }
+    }
+}
+
+impl LegacyStatus {
+    pub fn kick_string(&self, ping: &LegacyPing) -> String {
+        match ping {
+            // `§` separates the fields, so the motd can not have any formatting.
+            LegacyPing::Beta => format!(
+                "{}\u{a7}{}\u{a7}{}",
+                TextComponent::from_legacy(&self.motd).to_plain(),
+                self.online,
+                self.max
+            ),
+            LegacyPing::V1_4 | LegacyPing::V1_6 { .. } => format!(
+                "\u{a7}1\0{}\0{}\0{}\0{}\0{}",
+                self.protocol_version, self.version_name, self.motd, self.online, self.max
+            ),
+        }
+    }
+}
+
+async fn read_utf16<R: AsyncRead + Unpin>(reader: &mut R) -> Result<String> {
+    let len = reader
+        .read_u16()
+        .await
+        .context("Failed to read string length")?;
+
+    let mut units = Vec::with_capacity(len as usize);
+    for _ in 0..len {
+        units.push(reader.read_u16().await.context("Failed to read string")?);
+    }
+    String::from_utf16(&units).context("Invalid UTF-16 string")
+}
+
+async fn write_utf16<W: AsyncWrite + Unpin>(writer: &mut W, string: &str) -> Result<usize> {
+    let units = string.encode_utf16().collect::<Vec<_>>();
+    ensure!(
+        units.len() <= u16::MAX as usize,
+        "Too long string: {}",
+        units.len()
+    );
+
+    writer.write_u16(units.len() as u16).await?;
+    for unit in &units {
+        writer.write_u16(*unit).await?;
+    }
+    Ok(2 + units.len() * 2)
+}
+
+pub async fn read_legacy_ping<R: AsyncRead + Unpin>(reader: &mut R) -> Result<LegacyPing> {
+    let id = reader
+        .read_u8()
+        .await
+        .context("Failed to read legacy ping")?;
+    ensure!(id == LEGACY_PING_ID, "Not a legacy ping: {:#04x}", id);
+
+    match tokio::time::timeout(PAYLOAD_TIMEOUT, reader.read_u8()).await {
+        Err(_) => return Ok(LegacyPing::Beta),
+        Ok(payload) => {
+            let payload = payload.context("Failed to read legacy ping payload")?;
+            ensure!(
+                payload == PING_PAYLOAD,
+                "Unknown legacy ping payload: {:#04x}",
+                payload
+            );
+        }
+    }
+
+    // 1.4 clients send nothing more, while 1.6 clients immediately follow up with the plugin message.
+    match tokio::time::timeout(PAYLOAD_TIMEOUT, reader.read_u8()).await {
+        Err(_) => Ok(LegacyPing::V1_4),
+        Ok(id) => {
+            let id = id.context("Failed to read legacy plugin message")?;
+            ensure!(
+                id == PLUGIN_MESSAGE_ID,
+                "Unknown legacy packet: {:#04x}",
+                id
+            );
+
+            let channel = read_utf16(reader).await?;
+            if channel != PING_HOST_CHANNEL {
+                bail!("Unknown legacy channel: {}", channel);
+            }
+            let _data_len = reader.read_u16().await?;
+            let protocol_version = reader.read_u8().await?;
+            let host = read_utf16(reader).await?;
+            let port = reader.read_i32().await?;
+
+            Ok(LegacyPing::V1_6 {
+                protocol_version,
+                host,
+                port,
+            })
+        }
+    }
+}
+
+pub async fn write_legacy_kick<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    reason: &str,
+) -> Result<usize> {
+    writer.write_u8(LEGACY_KICK_ID).await?;
+    let written = write_utf16(writer, reason).await?;
+    writer
+        .flush()
+        .await
+        .context("Failed to flush legacy kick")?;
+
+    Ok(1 + written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{primitive::Chat, status::*};
+
+    fn status() -> LegacyStatus {
+        LegacyStatus {
+            protocol_version: LEGACY_PROTOCOL_VERSION,
+            version_name: "1.20.1".into(),
+            motd: "A cuber server".into(),
+            online: 1,
+            max: 20,
+        }
+    }
+
+    fn utf16(string: &str) -> Vec<u8> {
+        let mut buf = (string.encode_utf16().count() as u16)
+            .to_be_bytes()
+            .to_vec();
+        buf.extend(string.encode_utf16().flat_map(u16::to_be_bytes));
+        buf
+    }
+
+    #[tokio::test]
+    async fn ping_beta() {
+        let (mut player, mut server) = tokio::io::duplex(64);
+        player.write_all(&[0xfe]).await.unwrap();
+
+        assert_eq!(
+            read_legacy_ping(&mut server).await.unwrap(),
+            LegacyPing::Beta
+        );
+    }
+
+    #[tokio::test]
+    async fn ping_1_4() {
+        let (mut player, mut server) = tokio::io::duplex(64);
+        player.write_all(&[0xfe, 0x01]).await.unwrap();
+
+        assert_eq!(
+            read_legacy_ping(&mut server).await.unwrap(),
+            LegacyPing::V1_4
+        );
+    }
+
+    #[tokio::test]
+    async fn ping_1_6() {
+        let (mut player, mut server) = tokio::io::duplex(256);
+
+        let mut data = vec![74];
+        data.extend(utf16("localhost"));
+        data.extend(25565_i32.to_be_bytes());
+
+        let mut ping = vec![0xfe, 0x01, 0xfa];
+        ping.extend(utf16("MC|PingHost"));
+        ping.extend((data.len() as u16).to_be_bytes());
+        ping.extend(data);
+        player.write_all(&ping).await.unwrap();
+
+        assert_eq!(
+            read_legacy_ping(&mut server).await.unwrap(),
+            LegacyPing::V1_6 {
+                protocol_version: 74,
+                host: "localhost".into(),
+                port: 25565,
+            }
+        );
+    }
+
+    #[test]
+    fn kick_string() {
+        assert_eq!(
+            status().kick_string(&LegacyPing::Beta),
+            "A cuber server\u{a7}1\u{a7}20"
+        );
+        assert_eq!(
+            status().kick_string(&LegacyPing::V1_4),
+            "\u{a7}1\u{0}127\u{0}1.20.1\u{0}A cuber server\u{0}1\u{0}20"
+        );
+    }
+
+    #[tokio::test]
+    async fn kick_encoding() {
+        let mut buf = Vec::new();
+        let written = write_legacy_kick(&mut buf, "\u{a7}1").await.unwrap();
+
+        assert_eq!(buf, [0xff, 0x00, 0x02, 0x00, 0xa7, 0x00, 0x31]);
+        assert_eq!(written, buf.len());
+    }
+
+    #[test]
+    fn from_server_status() {
+        let status = ServerStatus {
+            version: StatusVersion {
+                name: "1.20.1".into(),
+                protocol: 763,
+            },
+            players: Some(StatusPlayers {
+                max: 20,
+                online: 1,
+                sample: vec![],
+            }),
+            description: Some(Chat::text("A cuber server")),
+            favicon: None,
+            enforces_secure_chat: false,
+        };
+
+        assert_eq!(LegacyStatus::from(&status), super::tests::status());
+    }
+
+    #[test]
+    fn kick_string_has_legacy_protocol() {
+        let status = LegacyStatus::from(&ServerStatus {
+            version: StatusVersion {
+                name: "1.20.1".into(),
+                protocol: 763,
+            },
+            players: None,
+            description: None,
+            favicon: None,
+            enforces_secure_chat: false,
+        });
+
+        let kick = status.kick_string(&LegacyPing::V1_6 {
+            protocol_version: 78,
+            host: "localhost".into(),
+            port: 25565,
+        });
+        assert_eq!(kick.split('\0').nth(1), Some("127"));
+    }
+}
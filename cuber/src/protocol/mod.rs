@@ -1,74 +1,353 @@
+pub mod chat;
+pub mod chunk;
 pub mod client_bound;
 pub mod common;
+pub mod encryption;
+pub mod legacy;
 pub mod primitive;
 pub mod server_bound;
+pub mod session;
+pub mod status;
+pub mod version;
 
-use std::io::{Cursor, Read, Write};
-use tokio::io::{AsyncReadExt, AsyncWrite, BufReader, BufWriter};
+use std::{
+    io::{Cursor, Read, Write},
+    marker::PhantomData,
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, BufReader, BufWriter, ReadHalf, WriteHalf},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+};
 
-use client_bound::ClientBoundPacket;
+use client_bound::{
+    ClientBoundPacket, EncryptionRequest, FinishConfiguration, LoginSuccess, SetCompression,
+};
 use common::*;
-use primitive::leb128::{async_read_var_int, build_var_int};
-use server_bound::{Handshaking, Login, PacketCluster, Play, Status};
+use encryption::{generate_verify_token, CipherReader, CipherWriter, ServerKey, SharedSecret};
+use legacy::{LegacyPing, LegacyStatus};
+use primitive::leb128::{async_read_var_int, build_var_int, read_var_int, var_int_len};
+use server_bound::{Configuration, Handshaking, Login, PacketCluster, Play, Status};
+use version::ProtocolVersion;
+
+use bytes::{Buf, Bytes};
+use flate2::{read::ZlibDecoder, write::ZlibEncoder};
 
 pub use anyhow::Result;
+use anyhow::{bail, ensure, Context as _};
 
 pub trait Encodable {
-    fn encode<T: Write>(&self, writer: &mut T) -> usize;
+    fn encode<T: Write>(&self, writer: &mut T) -> Result<usize>;
+    // the exact number of bytes `encode` writes, so that length prefixes and buffers can be sized up front.
+    fn encoded_len(&self) -> usize;
 }
 pub trait Decodable: Sized {
     fn decode<T: Read>(reader: &mut T) -> Result<Self>;
+    // decodes from the front of `buf` and advances it. byte fields can slice `buf` here instead of copying.
+    fn decode_bytes(buf: &mut Bytes) -> Result<Self> {
+        decode_from_slice(buf, |reader| Self::decode(reader))
+    }
+}
+// for fields with a protocol defined maximum length, used by `#[cuber(max_len = N)]` in the derive.
+pub trait BoundedDecodable: Decodable {
+    fn decode_bounded<T: Read>(reader: &mut T, max_len: usize) -> Result<Self>;
+    fn decode_bytes_bounded(buf: &mut Bytes, max_len: usize) -> Result<Self> {
+        decode_from_slice(buf, |reader| Self::decode_bounded(reader, max_len))
+    }
+}
+
+// runs a reader based decode over `buf`, and advances it by what was consumed.
+// for `decode_bytes` of types that have nothing to slice, and for the derive's `with` modules.
+pub fn decode_from_slice<V>(
+    buf: &mut Bytes,
+    decode: impl FnOnce(&mut &[u8]) -> Result<V>,
+) -> Result<V> {
+    let mut slice: &[u8] = buf;
+    let decoded = decode(&mut slice)?;
+    let consumed = buf.len() - slice.len();
+    buf.advance(consumed);
+    Ok(decoded)
 }
 
+pub const MAX_PACKET_LENGTH: usize = 2097151; // the largest 3 byte VarInt
+pub const MAX_DATA_LENGTH: usize = 8388608; // uncompressed size of a compressed packet
+
 #[derive(Debug)]
-pub struct Client {
-    reader: BufReader<tokio::net::tcp::OwnedReadHalf>,
-    writer: BufWriter<tokio::net::tcp::OwnedWriteHalf>,
+pub struct Client<S = Handshaking, R = OwnedReadHalf, W = OwnedWriteHalf> {
+    reader: CipherReader<BufReader<R>>,
+    writer: CipherWriter<BufWriter<W>>,
 
-    state: State,
     compression: Compression,
+    compression_threshold: usize,
     encryption: Encryption,
+    strictness: Strictness,
+    protocol_version: ProtocolVersion,
+
+    _state: PhantomData<fn() -> S>,
 }
 
 impl Client {
-    pub fn from_stream(stream: tokio::net::TcpStream) -> Self {
+    pub fn from_stream(stream: TcpStream) -> Self {
         let (reader, writer) = stream.into_split();
-        let reader = BufReader::new(reader);
-        let writer = BufWriter::new(writer);
+        Self::new(reader, writer)
+    }
+}
 
+impl<S: AsyncRead + AsyncWrite> Client<Handshaking, ReadHalf<S>, WriteHalf<S>> {
+    // for transports without an owned split, e.g. `tokio::io::duplex` or a TLS stream.
+    pub fn from_io(stream: S) -> Self {
+        let (reader, writer) = tokio::io::split(stream);
+        Self::new(reader, writer)
+    }
+}
+
+impl<R, W> Client<Handshaking, R, W>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    pub fn new(reader: R, writer: W) -> Self {
         Self {
-            reader,
-            writer,
-            state: State::Handshaking,
+            reader: CipherReader::new(BufReader::new(reader)),
+            writer: CipherWriter::new(BufWriter::new(writer)),
 
             compression: Compression::Disabled,
+            compression_threshold: 0,
             encryption: Encryption::Disabled,
+            strictness: Strictness::Strict,
+            protocol_version: ProtocolVersion::DEFAULT,
+
+            _state: PhantomData,
         }
     }
 
-    pub async fn send_built_packet(&mut self, packet: BuiltPacket) -> usize {
-        assert_eq!(self.compression, Compression::Disabled); // TODO
-        assert_eq!(self.encryption, Encryption::Disabled); // TODO
+    // legacy pings are not framed by a VarInt, so they have to be told apart before the handshake.
+    pub async fn is_legacy_ping(&mut self) -> Result<bool> {
+        use tokio::io::AsyncBufReadExt;
+
+        let buf = self
+            .reader
+            .get_mut()
+            .fill_buf()
+            .await
+            .context("Failed to peek first byte")?;
+        Ok(buf.first() == Some(&legacy::LEGACY_PING_ID))
+    }
+    pub async fn receive_legacy_ping(&mut self) -> Result<LegacyPing> {
+        legacy::read_legacy_ping(&mut self.reader).await
+    }
+    // the kick packet carries the status, and the connection should be closed after it.
+    pub async fn send_legacy_status(
+        &mut self,
+        ping: &LegacyPing,
+        status: &LegacyStatus,
+    ) -> Result<usize> {
+        legacy::write_legacy_kick(&mut self.writer, &status.kick_string(ping)).await
+    }
 
-        send_packet_plain_no_compression(&mut self.writer, packet).await
+    pub fn into_status(self) -> Client<Status, R, W> {
+        self.transition()
     }
-    pub async fn send_packet<T>(&mut self, packet: T) -> usize
+    pub fn into_login(self) -> Client<Login, R, W> {
+        self.transition()
+    }
+}
+
+impl<S, R, W> Client<S, R, W>
+where
+    S: ConnectionState,
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    fn transition<N: ConnectionState>(self) -> Client<N, R, W> {
+        Client {
+            reader: self.reader,
+            writer: self.writer,
+
+            compression: self.compression,
+            compression_threshold: self.compression_threshold,
+            encryption: self.encryption,
+            strictness: self.strictness,
+            protocol_version: self.protocol_version,
+
+            _state: PhantomData,
+        }
+    }
+
+    pub fn state(&self) -> State {
+        S::STATE
+    }
+    pub fn set_strictness(&mut self, strictness: Strictness) {
+        self.strictness = strictness;
+    }
+    pub fn protocol_version(&self) -> ProtocolVersion {
+        self.protocol_version
+    }
+    // packets are built and parsed for this version from now on, usually the one in `Handshake`.
+    pub fn set_protocol_version(&mut self, protocol_version: ProtocolVersion) {
+        self.protocol_version = protocol_version;
+    }
+
+    pub async fn send_built_packet(&mut self, packet: BuiltPacket) -> Result<usize> {
+        use tokio::io::AsyncWriteExt;
+
+        let written = match self.compression {
+            Compression::Enabled => {
+                send_packet_plain_with_compression(
+                    &mut self.writer,
+                    packet,
+                    self.compression_threshold,
+                )
+                .await
+            }
+            Compression::Disabled | Compression::Handhsaking => {
+                send_packet_plain_no_compression(&mut self.writer, packet).await
+            }
+        }?;
+        self.writer
+            .flush()
+            .await
+            .context("Failed to flush packet")?;
+
+        Ok(written)
+    }
+    pub async fn send_packet<T>(&mut self, packet: T) -> Result<usize>
     where
-        T: ClientBoundPacket,
+        T: ClientBoundPacket<ValidState = S>,
     {
-        packet.verify(self.state);
+        let packet = self
+            .protocol_version
+            .scope(|| packet.to_packet())
+            .with_context(|| format!("Failed to build {}", std::any::type_name::<T>()))?;
+        self.send_built_packet(packet).await
+    }
+
+    pub async fn receive_raw_packet(&mut self) -> Result<ReceivedPacket> {
+        let packet = match self.compression {
+            Compression::Enabled => {
+                receive_packet_plain_with_compression(&mut self.reader, self.compression_threshold)
+                    .await
+            }
+            Compression::Disabled | Compression::Handhsaking => {
+                receive_packet_plain_no_compression(&mut self.reader).await
+            }
+        }?;
+
+        Ok(packet.with_strictness(self.strictness))
+    }
+    pub async fn receive_packet(&mut self) -> Result<S> {
+        let mut packet = self.receive_raw_packet().await?;
+        self.parse_packet(&mut packet)
+    }
+    // a packet that fails to parse does not break the framing, unlike errors of `receive_raw_packet`.
+    // warnings of `Strictness::Warn` are left in `packet`.
+    pub fn parse_packet(&self, packet: &mut ReceivedPacket) -> Result<S> {
+        self.protocol_version.scope(|| S::parse(packet))
+    }
+}
+
+impl<R, W> Client<Login, R, W>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    // negative threshold disables compression.
+    pub async fn set_compression(&mut self, threshold: i32) -> Result<usize> {
+        assert_eq!(self.compression, Compression::Disabled);
+
+        self.compression = Compression::Handhsaking;
+        let written = self
+            .send_packet(SetCompression {
+                threshold: threshold.into(),
+            })
+            .await?;
+
+        if threshold < 0 {
+            self.compression = Compression::Disabled;
+        } else {
+            self.compression = Compression::Enabled;
+            self.compression_threshold = threshold as _;
+        }
 
-        self.send_built_packet(packet.to_packet()).await
+        Ok(written)
     }
 
-    pub async fn receive_packet(&mut self) -> Result<ReceivedPacket> {
-        assert_eq!(self.compression, Compression::Disabled); // TODO
-        assert_eq!(self.encryption, Encryption::Disabled); // TODO
+    // returns the shared secret, which is needed to compute the server hash.
+    pub async fn enable_encryption(&mut self, key: &ServerKey) -> Result<SharedSecret> {
+        assert_eq!(self.encryption, Encryption::Disabled);
 
-        receive_packet_plain_no_compression(&mut self.reader).await
+        let verify_token = generate_verify_token();
+        self.send_packet(EncryptionRequest {
+            server_id: "".into(),
+            public_key: key.public_key_der().into(),
+            verify_token: verify_token.as_slice().into(),
+        })
+        .await?;
+        self.encryption = Encryption::Handshaking;
+
+        let response = self.receive_packet().await?.assume_encryption_response()?;
+
+        let token = key
+            .decrypt(&response.verify_token.bytes)
+            .context("Failed to decrypt verify token")?;
+        ensure!(token == verify_token, "Verify token mismatch");
+        let shared_secret = key.decrypt_shared_secret(&response.shared_secret.bytes)?;
+
+        self.reader.enable(&shared_secret);
+        self.writer.enable(&shared_secret);
+        self.encryption = Encryption::Enabled;
+
+        Ok(shared_secret)
     }
-    pub fn set_state(&mut self, state: State) {
-        self.state = state;
+
+    // since 1.20.2, the client acknowledges the login and is configured before it starts playing.
+    pub async fn finish_login(mut self, success: LoginSuccess) -> Result<LoginFinished<R, W>> {
+        self.send_packet(success).await?;
+
+        if self.protocol_version < ProtocolVersion::V1_20_2 {
+            return Ok(LoginFinished::Play(self.transition()));
+        }
+
+        self.receive_packet().await?.assume_login_acknowledged()?;
+        Ok(LoginFinished::Configuration(self.transition()))
+    }
+}
+
+#[derive(Debug)]
+pub enum LoginFinished<R, W> {
+    Play(Client<Play, R, W>),
+    Configuration(Client<Configuration, R, W>),
+}
+
+impl<R, W> LoginFinished<R, W> {
+    pub fn assume_play(self) -> Result<Client<Play, R, W>> {
+        match self {
+            Self::Play(client) => Ok(client),
+            Self::Configuration(_) => bail!("expect Play but found Configuration"),
+        }
+    }
+}
+
+impl<R, W> Client<Configuration, R, W>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    // packets sent by the client until it acknowledges are dropped, e.g. its brand and information.
+    // so are the ones that fail to parse, like a resource pack response, only a broken frame is an error.
+    pub async fn finish_configuration(mut self) -> Result<Client<Play, R, W>> {
+        self.send_packet(FinishConfiguration {}).await?;
+
+        loop {
+            let mut packet = self.receive_raw_packet().await?;
+            if let Ok(Configuration::AcknowledgeFinishConfiguration(_)) =
+                self.parse_packet(&mut packet)
+            {
+                return Ok(self.transition());
+            }
+        }
     }
 }
 
@@ -80,37 +359,115 @@ pub struct BuiltPacket {
 pub async fn send_packet_plain_no_compression<T: AsyncWrite + Unpin>(
     writer: &mut T,
     packet: BuiltPacket,
-) -> usize {
+) -> Result<usize> {
     use tokio::io::AsyncWriteExt;
-    let mut data = build_var_int(packet.buf.len() as _);
-    data.extend_from_slice(&packet.buf);
 
-    writer.write_all(&data).await.unwrap();
+    writer
+        .write_all(&build_var_int(packet.buf.len() as _))
+        .await
+        .context("Failed to send packet")?;
+    writer
+        .write_all(&packet.buf)
+        .await
+        .context("Failed to send packet")?;
 
-    packet.buf.len()
+    Ok(packet.buf.len())
+}
+
+pub async fn send_packet_plain_with_compression<T: AsyncWrite + Unpin>(
+    writer: &mut T,
+    packet: BuiltPacket,
+    threshold: usize,
+) -> Result<usize> {
+    use tokio::io::AsyncWriteExt;
+
+    // data length and the packet, sized for the uncompressed case as zlib rarely grows it
+    let mut body = Vec::with_capacity(var_int_len(packet.buf.len() as _) + packet.buf.len());
+    if packet.buf.len() < threshold {
+        body.extend_from_slice(&build_var_int(0));
+        body.extend_from_slice(&packet.buf);
+    } else {
+        body.extend_from_slice(&build_var_int(packet.buf.len() as _));
+
+        let mut encoder = ZlibEncoder::new(body, flate2::Compression::default());
+        encoder
+            .write_all(&packet.buf)
+            .context("Failed to compress packet")?;
+        body = encoder.finish().context("Failed to compress packet")?;
+    }
+
+    writer
+        .write_all(&build_var_int(body.len() as _))
+        .await
+        .context("Failed to send packet")?;
+    writer
+        .write_all(&body)
+        .await
+        .context("Failed to send packet")?;
+
+    Ok(packet.buf.len())
 }
 
 #[derive(Clone, Debug)]
 pub struct ReceivedPacket {
-    buf: Cursor<Box<[u8]>>, // plain data.
+    buf: Bytes, // plain data, not read yet.
+    strictness: Strictness,
+    warning: Option<String>, // left by parsing with `Strictness::Warn`
 }
 
 impl Read for ReceivedPacket {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        std::io::Read::read(&mut self.buf, buf)
+        let len = buf.len().min(self.buf.len());
+        self.buf.copy_to_slice(&mut buf[..len]);
+        Ok(len)
     }
 }
 
-impl Drop for ReceivedPacket {
-    fn drop(&mut self) {
-        let remain = self.buf.get_ref().len() - self.buf.position() as usize;
-        if remain != 0 {
-            panic!("Unprocessed byte sequence remains. {} byte(s)", remain);
+impl ReceivedPacket {
+    pub fn new(buf: impl Into<Bytes>) -> Self {
+        Self {
+            buf: buf.into(),
+            strictness: Strictness::Strict,
+            warning: None,
         }
     }
-}
+    pub fn with_strictness(mut self, strictness: Strictness) -> Self {
+        self.strictness = strictness;
+        self
+    }
+    pub fn remaining(&self) -> usize {
+        self.buf.len()
+    }
+    // the unread part of the packet, sharing the received buffer.
+    pub fn remaining_bytes(&self) -> &Bytes {
+        &self.buf
+    }
+    pub fn finish(&self) -> Result<()> {
+        let remain = self.remaining();
+        ensure!(
+            remain == 0,
+            "Unprocessed byte sequence remains. {} byte(s)",
+            remain
+        );
+        Ok(())
+    }
+    // checks `finish` according to the strictness of this packet.
+    // with `Strictness::Warn`, the failure is returned as a warning for the caller to report.
+    pub fn finish_with_strictness(&self) -> Result<Option<anyhow::Error>> {
+        match self.strictness {
+            Strictness::Strict => self.finish().map(|_| None),
+            Strictness::Warn => Ok(self.finish().err()),
+            Strictness::Ignore => Ok(None),
+        }
+    }
+    pub fn set_warning(&mut self, warning: String) {
+        self.warning = Some(warning);
+    }
+    // the warning left by parsing this packet, if any.
+    pub fn take_warning(&mut self) -> Option<String> {
+        self.warning.take()
+    }
 
-impl ReceivedPacket {
     pub fn as_handshaking(mut self) -> Result<Handshaking, (anyhow::Error, Self)> {
         Handshaking::parse(&mut self).map_err(|e| (e, self))
     }
@@ -125,16 +482,493 @@ impl ReceivedPacket {
     }
 }
 
+async fn read_packet_length<T: AsyncRead + Unpin>(reader: &mut T) -> Result<usize> {
+    let length = async_read_var_int(reader).await?.1;
+    ensure!(
+        (0..=MAX_PACKET_LENGTH as i32).contains(&length),
+        "Invalid packet length: {} (max {})",
+        length,
+        MAX_PACKET_LENGTH
+    );
+    Ok(length as _)
+}
+
 // TODO: change by connection configure.
-pub async fn receive_packet_plain_no_compression<T: tokio::io::AsyncRead + Unpin>(
+pub async fn receive_packet_plain_no_compression<T: AsyncRead + Unpin>(
+    reader: &mut T,
+) -> Result<ReceivedPacket> {
+    let length = read_packet_length(reader).await?;
+    let mut buffer = vec![0; length];
+
+    reader.read_exact(&mut buffer).await?;
+
+    Ok(ReceivedPacket::new(buffer))
+}
+
+pub async fn receive_packet_plain_with_compression<T: AsyncRead + Unpin>(
     reader: &mut T,
+    threshold: usize,
 ) -> Result<ReceivedPacket> {
-    let length = async_read_var_int(reader).await?.1 as _;
+    let length = read_packet_length(reader).await?;
     let mut buffer = vec![0; length];
 
     reader.read_exact(&mut buffer).await?;
 
-    Ok(ReceivedPacket {
-        buf: Cursor::new(buffer.into_boxed_slice()),
-    })
+    let mut body = Cursor::new(buffer.as_slice());
+    let data_length = read_var_int(&mut body)
+        .context("Failed to read data length")?
+        .1;
+    let position = body.position() as usize;
+
+    if data_length == 0 {
+        // not compressed, so the packet shares the received buffer.
+        return Ok(ReceivedPacket::new(Bytes::from(buffer).slice(position..)));
+    }
+    let compressed = &buffer[position..];
+
+    ensure!(
+        (0..=MAX_DATA_LENGTH as i32).contains(&data_length),
+        "Invalid data length: {} (max {})",
+        data_length,
+        MAX_DATA_LENGTH
+    );
+    let data_length = data_length as usize;
+    ensure!(
+        threshold <= data_length,
+        "Badly compressed packet: size of {} is below threshold of {}",
+        data_length,
+        threshold
+    );
+
+    let mut data = Vec::with_capacity(data_length);
+    ZlibDecoder::new(compressed)
+        .take(data_length as u64 + 1)
+        .read_to_end(&mut data)
+        .context("Failed to decompress packet")?;
+    ensure!(
+        data.len() == data_length,
+        "Decompressed length mismatch: expected {}, but got {}",
+        data_length,
+        data.len()
+    );
+
+    Ok(ReceivedPacket::new(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // PluginMessage { channel: "minecraft:brand", data: "vanilla" * 40 } in the play state,
+    // framed by hand with threshold 256. this is no capture of a vanilla server; the body is
+    // `zlib.compress(data, 6)` of python, so it only shows that a zlib stream of another
+    // implementation is accepted, at the level `java.util.zip.Deflater` uses by default.
+    const ZLIB_BRAND_FRAME: [u8; 38] = [
+        0x25, 0xa9, 0x02, 0x78, 0x9c, 0x13, 0xe7, 0xcf, 0xcd, 0xcc, 0x4b, 0x4d, 0x2e, 0x4a, 0x4c,
+        0x2b, 0xb1, 0x4a, 0x2a, 0x4a, 0xcc, 0x4b, 0x29, 0x4b, 0xcc, 0xcb, 0xcc, 0xc9, 0x49, 0x1c,
+        0xa5, 0xd0, 0x28, 0x00, 0xa8, 0xfc, 0x7a, 0x39,
+    ];
+    // ChangeDifficulty { new_difficulty: Peaceful, difficulty_locked: false } below the threshold.
+    const UNCOMPRESSED_DIFFICULTY: [u8; 5] = [0x04, 0x00, 0x0c, 0x00, 0x00];
+
+    fn brand_packet() -> Vec<u8> {
+        let mut data = vec![0x17, 15];
+        data.extend_from_slice(b"minecraft:brand");
+        data.extend_from_slice(&b"vanilla".repeat(40));
+        data
+    }
+
+    fn read_all(mut packet: ReceivedPacket) -> Vec<u8> {
+        let mut buf = Vec::new();
+        packet.read_to_end(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn finish_reports_remaining() {
+        let mut packet = ReceivedPacket::new(vec![1, 2, 3].into_boxed_slice());
+        assert_eq!(u8::decode(&mut packet).unwrap(), 1);
+
+        let e = packet.finish().unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Unprocessed byte sequence remains. 2 byte(s)"
+        );
+        assert_eq!(packet.remaining(), 2);
+    }
+
+    #[tokio::test]
+    async fn receive_too_long() {
+        // length 2097152 is one byte more than the limit.
+        let mut reader = [0x80, 0x80, 0x80, 0x01].as_slice();
+        receive_packet_plain_no_compression(&mut reader)
+            .await
+            .unwrap_err();
+
+        let mut reader = [0x80, 0x80, 0x80, 0x01].as_slice();
+        receive_packet_plain_with_compression(&mut reader, 256)
+            .await
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn receive_foreign_zlib_frame() {
+        let mut reader = ZLIB_BRAND_FRAME.as_slice();
+        let packet = receive_packet_plain_with_compression(&mut reader, 256)
+            .await
+            .unwrap();
+
+        assert_eq!(read_all(packet), brand_packet());
+        assert!(reader.is_empty());
+    }
+
+    #[tokio::test]
+    async fn receive_below_threshold() {
+        let mut reader = UNCOMPRESSED_DIFFICULTY.as_slice();
+        let packet = receive_packet_plain_with_compression(&mut reader, 256)
+            .await
+            .unwrap();
+
+        assert_eq!(read_all(packet), vec![0x0c, 0x00, 0x00]);
+    }
+
+    #[tokio::test]
+    async fn receive_compressed_below_threshold() {
+        let mut reader = ZLIB_BRAND_FRAME.as_slice();
+        receive_packet_plain_with_compression(&mut reader, 512)
+            .await
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn send_below_threshold() {
+        let mut buf = Vec::new();
+        let packet = BuiltPacket {
+            buf: vec![0x0c, 0x00, 0x00].into_boxed_slice(),
+        };
+        send_packet_plain_with_compression(&mut buf, packet, 256)
+            .await
+            .unwrap();
+
+        assert_eq!(buf, UNCOMPRESSED_DIFFICULTY);
+    }
+
+    #[tokio::test]
+    async fn compression_round_trip() {
+        let mut buf = Vec::new();
+        let packet = BuiltPacket {
+            buf: brand_packet().into_boxed_slice(),
+        };
+        send_packet_plain_with_compression(&mut buf, packet, 256)
+            .await
+            .unwrap();
+
+        // the deflate stream depends on the backend of flate2, so the frame is checked field by field.
+        let mut frame = buf.as_slice();
+        let (_, length) = read_var_int(&mut frame).unwrap();
+        assert_eq!(length as usize, frame.len());
+        let (_, data_length) = read_var_int(&mut frame).unwrap();
+        assert_eq!(data_length as usize, brand_packet().len());
+        let mut data = Vec::new();
+        ZlibDecoder::new(frame).read_to_end(&mut data).unwrap();
+        assert_eq!(data, brand_packet());
+
+        let packet = receive_packet_plain_with_compression(&mut buf.as_slice(), 256)
+            .await
+            .unwrap();
+        assert_eq!(read_all(packet), brand_packet());
+    }
+
+    #[tokio::test]
+    async fn client_over_duplex() {
+        use client_bound::PluginMessage;
+
+        let (server_side, player_side) = tokio::io::duplex(64);
+        let mut server = Client::from_io(server_side).into_login();
+        let mut player = Client::from_io(player_side);
+
+        let sender = tokio::spawn(async move {
+            server.set_compression(256).await.unwrap();
+            let mut server = server
+                .finish_login(LoginSuccess {
+                    uuid: uuid::Uuid::nil(),
+                    user_name: "a".into(),
+                    property: vec![].into(),
+                })
+                .await
+                .unwrap()
+                .assume_play()
+                .unwrap();
+            server
+                .send_packet(PluginMessage {
+                    channel: "minecraft:brand".into(),
+                    data: b"vanilla".repeat(40).into(),
+                })
+                .await
+                .unwrap();
+        });
+
+        let set_compression = player.receive_raw_packet().await.unwrap();
+        assert_eq!(read_all(set_compression), vec![0x03, 0x80, 0x02]);
+
+        player.compression = Compression::Enabled;
+        player.compression_threshold = 256;
+        let success = player.receive_raw_packet().await.unwrap();
+        assert_eq!(read_all(success)[0], 0x02);
+        let brand = player.receive_raw_packet().await.unwrap();
+        assert_eq!(read_all(brand), brand_packet());
+
+        sender.await.unwrap();
+    }
+
+    #[test]
+    fn packet_id_by_version() {
+        use client_bound::{ChangeDifficulty, FeatureFlags};
+
+        let difficulty = ChangeDifficulty {
+            new_difficulty: Difficulty::Easy,
+            difficulty_locked: true,
+        };
+        assert_eq!(difficulty.to_bytes().unwrap()[0], 0x0c);
+        ProtocolVersion::V1_20_2.scope(|| {
+            assert_eq!(difficulty.to_bytes().unwrap()[0], 0x0b);
+
+            let features = FeatureFlags {
+                features: vec![].into(),
+            };
+            let e = features.to_bytes().unwrap_err();
+            assert!(e
+                .to_string()
+                .contains("does not exist in protocol version 764"));
+        });
+    }
+
+    #[tokio::test]
+    async fn configuration_since_1_20_2() {
+        let (server_side, player_side) = tokio::io::duplex(1024);
+        let mut server = Client::from_io(server_side).into_login();
+        server.set_protocol_version(ProtocolVersion::V1_20_2);
+        let mut player = Client::from_io(player_side);
+
+        let handler = tokio::spawn(async move {
+            let server = server
+                .finish_login(LoginSuccess {
+                    uuid: uuid::Uuid::nil(),
+                    user_name: "a".into(),
+                    property: vec![].into(),
+                })
+                .await
+                .unwrap();
+            let LoginFinished::Configuration(server) = server else {
+                panic!("not in configuration");
+            };
+            server.finish_configuration().await.unwrap()
+        });
+
+        let success = player.receive_raw_packet().await.unwrap();
+        assert_eq!(read_all(success)[0], 0x02);
+        player
+            .send_built_packet(BuiltPacket {
+                buf: Box::new([0x03]),
+            })
+            .await
+            .unwrap();
+
+        let finish = player.receive_raw_packet().await.unwrap();
+        assert_eq!(read_all(finish), vec![0x02]);
+        // the brand sent before the acknowledgement is skipped.
+        let mut brand = vec![0x01, 0x0f];
+        brand.extend_from_slice(b"minecraft:brand");
+        player
+            .send_built_packet(BuiltPacket {
+                buf: brand.into_boxed_slice(),
+            })
+            .await
+            .unwrap();
+        player
+            .send_built_packet(BuiltPacket {
+                buf: Box::new([0x02]),
+            })
+            .await
+            .unwrap();
+
+        let server = handler.await.unwrap();
+        assert_eq!(server.state(), State::Play);
+        assert_eq!(server.protocol_version(), ProtocolVersion::V1_20_2);
+    }
+
+    #[tokio::test]
+    async fn configuration_skips_unparsable_packets() {
+        let (server_side, player_side) = tokio::io::duplex(1024);
+        let mut server = Client::from_io(server_side).into_login();
+        server.set_protocol_version(ProtocolVersion::V1_20_2);
+        let mut player = Client::from_io(player_side);
+
+        let handler = tokio::spawn(async move {
+            let server = server
+                .finish_login(LoginSuccess {
+                    uuid: uuid::Uuid::nil(),
+                    user_name: "a".into(),
+                    property: vec![].into(),
+                })
+                .await
+                .unwrap();
+            let LoginFinished::Configuration(server) = server else {
+                panic!("not in configuration");
+            };
+            server.finish_configuration().await
+        });
+
+        player.receive_raw_packet().await.unwrap();
+        player
+            .send_built_packet(BuiltPacket {
+                buf: Box::new([0x03]),
+            })
+            .await
+            .unwrap();
+        player.receive_raw_packet().await.unwrap();
+
+        // a resource pack response, which the configuration cluster does not know,
+        // and a keep alive with a trailing byte, which strict parsing rejects.
+        for buf in [vec![0x05, 0x00], vec![0x03, 0, 0, 0, 0, 0, 0, 0, 1, 0xff]] {
+            player
+                .send_built_packet(BuiltPacket {
+                    buf: buf.into_boxed_slice(),
+                })
+                .await
+                .unwrap();
+        }
+        player
+            .send_built_packet(BuiltPacket {
+                buf: Box::new([0x02]),
+            })
+            .await
+            .unwrap();
+
+        let server = handler.await.unwrap().unwrap();
+        assert_eq!(server.state(), State::Play);
+    }
+
+    #[tokio::test]
+    async fn configuration_fails_on_broken_frame() {
+        let (server_side, player_side) = tokio::io::duplex(1024);
+        let mut server = Client::from_io(server_side).into_login();
+        server.set_protocol_version(ProtocolVersion::V1_20_2);
+        let mut player = Client::from_io(player_side);
+
+        let handler = tokio::spawn(async move {
+            let server = server
+                .finish_login(LoginSuccess {
+                    uuid: uuid::Uuid::nil(),
+                    user_name: "a".into(),
+                    property: vec![].into(),
+                })
+                .await
+                .unwrap();
+            let LoginFinished::Configuration(server) = server else {
+                panic!("not in configuration");
+            };
+            server.finish_configuration().await
+        });
+
+        player.receive_raw_packet().await.unwrap();
+        player
+            .send_built_packet(BuiltPacket {
+                buf: Box::new([0x03]),
+            })
+            .await
+            .unwrap();
+        player.receive_raw_packet().await.unwrap();
+
+        // the peer goes away before acknowledging.
+        drop(player);
+        handler.await.unwrap().unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn legacy_ping_is_sniffed() {
+        use tokio::io::AsyncWriteExt;
+
+        let (server_side, mut player_side) = tokio::io::duplex(64);
+        let mut server = Client::from_io(server_side);
+
+        player_side.write_all(&[0xfe, 0x01]).await.unwrap();
+        assert!(server.is_legacy_ping().await.unwrap());
+
+        let ping = server.receive_legacy_ping().await.unwrap();
+        assert_eq!(ping, LegacyPing::V1_4);
+
+        let status = LegacyStatus {
+            protocol_version: legacy::LEGACY_PROTOCOL_VERSION,
+            version_name: "1.20.1".into(),
+            motd: "motd".into(),
+            online: 0,
+            max: 20,
+        };
+        server.send_legacy_status(&ping, &status).await.unwrap();
+        drop(server);
+
+        let mut kick = Vec::new();
+        player_side.read_to_end(&mut kick).await.unwrap();
+        assert_eq!(kick[0], 0xff);
+        assert_eq!(
+            kick.len(),
+            3 + status.kick_string(&ping).encode_utf16().count() * 2
+        );
+    }
+
+    #[tokio::test]
+    async fn handshake_is_not_legacy_ping() {
+        use tokio::io::AsyncWriteExt;
+
+        let (server_side, mut player_side) = tokio::io::duplex(64);
+        let mut server = Client::from_io(server_side);
+
+        player_side.write_all(&[0x10, 0x00]).await.unwrap();
+        assert!(!server.is_legacy_ping().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn receive_cluster() {
+        use server_bound::HandshakeNextState;
+
+        let (server_side, mut player_side) = tokio::io::duplex(64);
+        let mut server = Client::from_io(server_side);
+
+        // Handshake { protocol_version: 763, server_address: "localhost", server_port: 25565, next_state: Login }
+        let mut handshake = vec![0x00, 0xfb, 0x05, 0x09];
+        handshake.extend_from_slice(b"localhost");
+        handshake.extend_from_slice(&[0x63, 0xdd, 0x02]);
+        send_packet_plain_no_compression(
+            &mut player_side,
+            BuiltPacket {
+                buf: handshake.into_boxed_slice(),
+            },
+        )
+        .await
+        .unwrap();
+
+        let handshake = server.receive_packet().await.unwrap().unwrap_handshake();
+        assert_eq!(handshake.protocol_version.0, 763);
+        assert_eq!(handshake.next_state, HandshakeNextState::Login);
+
+        let server = server.into_login();
+        assert_eq!(server.state(), State::Login);
+    }
+
+    #[tokio::test]
+    async fn send_to_disconnected_peer() {
+        use client_bound::Disconnect;
+
+        let (server_side, player_side) = tokio::io::duplex(64);
+        let mut server = Client::from_io(server_side).into_login();
+        drop(player_side);
+
+        server
+            .send_packet(Disconnect {
+                chat: primitive::Chat::text("bye"),
+            })
+            .await
+            .unwrap_err();
+    }
 }
@@ -4,20 +4,28 @@ use std::{
     slice::{Iter, IterMut},
 };
 
+use bytes::Bytes;
 use deriver::{Decodable, Encodable};
 
-use crate::protocol::{Decodable, Encodable};
 use super::VarInt;
+use crate::protocol::{BoundedDecodable, Decodable, Encodable};
 
-use anyhow::{Result, Context as _, ensure};
+use anyhow::{ensure, Context as _, Result};
+
+// upper bound of the capacity reserved from a length prefix, before the elements are actually read.
+const MAX_PREALLOCATED_ELEMENTS: usize = 4096;
+
+// where an array ends when decoding.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum ArrayBound {
+    Elements(usize), // exactly this number of elements
+    Bytes(usize),    // elements filling exactly this number of bytes
+    Rest,            // elements up to the end of the packet
+}
 
 pub trait ArrayLength: Sized {
     fn from(write_object: usize, write_bytes: usize) -> Self;
-    fn got_element(&mut self, read_object: usize, read_bytes: usize);
-    fn has_next(&self) -> bool;
-    fn is_end(&self) -> bool {
-        !self.has_next()
-    }
+    fn bound(&self) -> Result<ArrayBound>;
 }
 
 #[derive(Encodable, Decodable, PartialEq, Eq, Clone, Copy, Hash)]
@@ -31,27 +39,26 @@ impl ArrayLength for VarIntLength {
             len: VarInt(write_object as _),
         }
     }
-    fn got_element(&mut self, read_object: usize, _read_bytes: usize) {
-        self.len.0 -= read_object as i32;
-    }
-    fn has_next(&self) -> bool {
-        self.len.0 > 0
+    fn bound(&self) -> Result<ArrayBound> {
+        ensure!(self.len.0 >= 0, "Invalid array length: {}", self.len.0);
+        Ok(ArrayBound::Elements(self.len.0 as usize))
     }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
-pub struct FixedLength<const L: usize> {
-    remain: usize,
-}
+pub struct FixedLength<const L: usize>;
 
 impl<const L: usize> Encodable for FixedLength<L> {
-    fn encode<T: Write>(&self, _writer: &mut T) -> usize {
+    fn encode<T: Write>(&self, _writer: &mut T) -> Result<usize> {
+        Ok(0)
+    }
+    fn encoded_len(&self) -> usize {
         0
     }
 }
 impl<const L: usize> Decodable for FixedLength<L> {
     fn decode<T: Read>(_reader: &mut T) -> Result<Self> {
-        Ok(Self { remain: L })
+        Ok(Self)
     }
 }
 impl<const L: usize> ArrayLength for FixedLength<L> {
@@ -62,13 +69,10 @@ impl<const L: usize> ArrayLength for FixedLength<L> {
                 L, write_object
             );
         }
-        Self { remain: L }
-    }
-    fn got_element(&mut self, read_object: usize, _read_bytes: usize) {
-        self.remain -= read_object;
+        Self
     }
-    fn has_next(&self) -> bool {
-        self.remain > 0
+    fn bound(&self) -> Result<ArrayBound> {
+        Ok(ArrayBound::Elements(L))
     }
 }
 
@@ -83,40 +87,25 @@ impl ArrayLength for VarIntLengthInBytes {
             len: VarInt(write_bytes as _),
         }
     }
-    fn got_element(&mut self, _read_object: usize, read_bytes: usize) {
-        self.len.0 -= read_bytes as i32;
-    }
-    fn has_next(&self) -> bool {
-        self.len.0 > 0
+    fn bound(&self) -> Result<ArrayBound> {
+        ensure!(
+            self.len.0 >= 0,
+            "Invalid array length in bytes: {}",
+            self.len.0
+        );
+        Ok(ArrayBound::Bytes(self.len.0 as usize))
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+#[derive(Encodable, Decodable, Debug, PartialEq, Eq, Clone, Copy, Hash)]
 pub struct PacketInferredInBytes;
-impl Encodable for PacketInferredInBytes {
-    fn encode<T: Write>(&self, _writer: &mut T) -> usize {
-        0
-    }
-}
-impl Decodable for PacketInferredInBytes {
-    fn decode<T: Read>(_reader: &mut T) -> Result<Self> {
-        Ok(Self)
-    }
-}
 
 impl ArrayLength for PacketInferredInBytes {
     fn from(_write_object: usize, _write_bytes: usize) -> Self {
         Self
     }
-    fn got_element(&mut self, _read_object: usize, _read_bytes: usize) {
-        // ah, ok.
-    }
-    // PacketInferred では、要素数がパケット依存なので has_next = true にしつつ is_ned = true にする
-    fn has_next(&self) -> bool {
-        true
-    }
-    fn is_end(&self) -> bool {
-        true
+    fn bound(&self) -> Result<ArrayBound> {
+        Ok(ArrayBound::Rest)
     }
 }
 
@@ -125,71 +114,184 @@ pub struct Array<L, T> {
     pub inner: Vec<T>,
     pub _phantom: PhantomData<fn(L) -> ()>,
 }
-impl<L, Inner> Encodable for Array<L, Inner>
+impl<L, Inner> Array<L, Inner>
 where
     Inner: Encodable,
     L: Encodable + ArrayLength,
 {
-    fn encode<T: Write>(&self, writer: &mut T) -> usize {
-        let mut written = 0;
-        let mut tmp_buf = Vec::with_capacity(core::mem::size_of::<Inner>() * self.inner.len()); // for performance
-
-        let object_num = self.inner.len();
-        let wrote_num = self
-            .iter()
-            .map(|inner| inner.encode(&mut tmp_buf))
-            .sum::<usize>();
+    // encodes elements with the length prefix of `L`, without wrapping them in an `Array`
+    pub fn encode_slice<T: Write>(elements: &[Inner], writer: &mut T) -> Result<usize> {
+        let bytes = elements.iter().map(Encodable::encoded_len).sum();
 
-        let l = L::from(object_num, wrote_num);
-        written += l.encode(writer);
-        written += tmp_buf.len();
+        let mut written = L::from(elements.len(), bytes).encode(writer)?;
+        for inner in elements {
+            written += inner.encode(writer).context("could not write array")?;
+        }
 
-        writer.write_all(&tmp_buf).unwrap();
+        Ok(written)
+    }
+    pub fn encoded_len_slice(elements: &[Inner]) -> usize {
+        let bytes = elements.iter().map(Encodable::encoded_len).sum();
 
-        written
+        L::from(elements.len(), bytes).encoded_len() + bytes
     }
 }
-impl<L, Inner> Decodable for Array<L, Inner>
+impl<L, Inner> Encodable for Array<L, Inner>
+where
+    Inner: Encodable,
+    L: Encodable + ArrayLength,
+{
+    fn encode<T: Write>(&self, writer: &mut T) -> Result<usize> {
+        Self::encode_slice(&self.inner, writer)
+    }
+    fn encoded_len(&self) -> usize {
+        Self::encoded_len_slice(&self.inner)
+    }
+}
+impl<L, Inner> Array<L, Inner>
 where
     Inner: Decodable,
     L: Decodable + ArrayLength,
 {
-    fn decode<Outer: Read>(reader: &mut Outer) -> Result<Self> {
-        struct ReadCountWrapper<Inner> {
-            inner: Inner,
-            count: usize,
-        }
-        impl<Inner> Read for ReadCountWrapper<Inner>
-        where
-            Inner: Read,
-        {
-            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-                let read = self.inner.read(buf)?;
-                self.count += read;
-                Ok(read)
+    // decodes elements prefixed with the length of `L` into a plain `Vec`
+    pub fn decode_vec<Outer: Read>(reader: &mut Outer) -> Result<Vec<Inner>> {
+        Self::decode_vec_bounded(reader, usize::MAX)
+    }
+    pub fn decode_vec_bounded<Outer: Read>(
+        reader: &mut Outer,
+        max_len: usize,
+    ) -> Result<Vec<Inner>> {
+        let length: L = L::decode(reader).context("Failed to decode array length")?;
+
+        match length.bound()? {
+            ArrayBound::Elements(len) => {
+                // checked before reading, so that a bogus length does not allocate.
+                ensure!(
+                    len <= max_len,
+                    "Array is too long: {} (max {})",
+                    len,
+                    max_len
+                );
+
+                let mut inner = Vec::with_capacity(len.min(MAX_PREALLOCATED_ELEMENTS));
+                for index in 0..len {
+                    inner.push(Self::decode_element(reader, index, max_len)?);
+                }
+                Ok(inner)
+            }
+            ArrayBound::Bytes(len) => {
+                let mut reader = reader.take(len as u64);
+                let mut inner = Vec::new();
+                while reader.limit() > 0 {
+                    let before = reader.limit();
+                    let element = Self::decode_element(&mut reader, inner.len(), max_len)?;
+                    ensure!(
+                        reader.limit() < before,
+                        "Array element {} is empty",
+                        inner.len()
+                    );
+                    inner.push(element);
+                }
+                Ok(inner)
+            }
+            ArrayBound::Rest => {
+                let mut rest = Vec::new();
+                reader
+                    .read_to_end(&mut rest)
+                    .context("could not read the rest of the packet")?;
+                Self::decode_all(rest.into(), max_len)
             }
         }
-        let reader = &mut ReadCountWrapper {
-            inner: reader,
-            count: 0,
-        };
+    }
+    fn decode_element<T: Read>(reader: &mut T, index: usize, max_len: usize) -> Result<Inner> {
+        ensure!(index < max_len, "Array is too long: more than {}", max_len);
+        Inner::decode(reader).with_context(|| format!("Failed to decode array element {}", index))
+    }
 
-        let mut remain_checker: L = L::decode(reader).context("Failed to decode array length")?;
-        let mut inner = Vec::new();
+    // like `decode_vec`, but slices `buf` for the elements instead of reading them through a copy
+    pub fn decode_vec_bytes(buf: &mut Bytes) -> Result<Vec<Inner>> {
+        Self::decode_vec_bytes_bounded(buf, usize::MAX)
+    }
+    pub fn decode_vec_bytes_bounded(buf: &mut Bytes, max_len: usize) -> Result<Vec<Inner>> {
+        let length: L = L::decode_bytes(buf).context("Failed to decode array length")?;
 
-        while let Ok(element) = Inner::decode(reader) {
-            inner.push(element);
-            remain_checker.got_element(1, reader.count);
+        match length.bound()? {
+            ArrayBound::Elements(len) => {
+                ensure!(
+                    len <= max_len,
+                    "Array is too long: {} (max {})",
+                    len,
+                    max_len
+                );
 
-            if !remain_checker.has_next() {
-                break;
+                let mut inner = Vec::with_capacity(len.min(MAX_PREALLOCATED_ELEMENTS));
+                for index in 0..len {
+                    inner.push(Self::decode_element_bytes(buf, index, max_len)?);
+                }
+                Ok(inner)
+            }
+            ArrayBound::Bytes(len) => {
+                ensure!(
+                    len <= buf.len(),
+                    "Array is truncated: {} byte(s) left of {}",
+                    buf.len(),
+                    len
+                );
+                Self::decode_all(buf.split_to(len), max_len)
             }
+            ArrayBound::Rest => Self::decode_all(std::mem::take(buf), max_len),
         }
+    }
+    fn decode_element_bytes(buf: &mut Bytes, index: usize, max_len: usize) -> Result<Inner> {
+        ensure!(index < max_len, "Array is too long: more than {}", max_len);
+        Inner::decode_bytes(buf)
+            .with_context(|| format!("Failed to decode array element {}", index))
+    }
+    // elements filling all of `buf`
+    fn decode_all(mut buf: Bytes, max_len: usize) -> Result<Vec<Inner>> {
+        let mut inner = Vec::new();
+        while !buf.is_empty() {
+            let before = buf.len();
+            let element = Self::decode_element_bytes(&mut buf, inner.len(), max_len)?;
+            ensure!(buf.len() < before, "Array element {} is empty", inner.len());
+            inner.push(element);
+        }
+        Ok(inner)
+    }
+}
+impl<L, Inner> Decodable for Array<L, Inner>
+where
+    Inner: Decodable,
+    L: Decodable + ArrayLength,
+{
+    fn decode<Outer: Read>(reader: &mut Outer) -> Result<Self> {
+        Ok(Self {
+            inner: Self::decode_vec(reader)?,
+            _phantom: PhantomData,
+        })
+    }
+    fn decode_bytes(buf: &mut Bytes) -> Result<Self> {
+        Ok(Self {
+            inner: Self::decode_vec_bytes(buf)?,
+            _phantom: PhantomData,
+        })
+    }
+}
 
-        ensure!(remain_checker.is_end(), "Array length mismatch");
-
+impl<L, Inner> BoundedDecodable for Array<L, Inner>
+where
+    Inner: Decodable,
+    L: Decodable + ArrayLength,
+{
+    fn decode_bounded<T: Read>(reader: &mut T, max_len: usize) -> Result<Self> {
         Ok(Self {
-            inner,
+            inner: Self::decode_vec_bounded(reader, max_len)?,
+            _phantom: PhantomData,
+        })
+    }
+    fn decode_bytes_bounded(buf: &mut Bytes, max_len: usize) -> Result<Self> {
+        Ok(Self {
+            inner: Self::decode_vec_bytes_bounded(buf, max_len)?,
             _phantom: PhantomData,
         })
     }
@@ -210,6 +312,11 @@ where
         .finish()
     }
 }
+impl<L, Inner> Default for Array<L, Inner> {
+    fn default() -> Self {
+        Vec::new().into()
+    }
+}
 impl<L, Inner> From<Vec<Inner>> for Array<L, Inner> {
     fn from(value: Vec<Inner>) -> Self {
         Self {
@@ -233,11 +340,11 @@ where
 
 impl<L, Inner> Array<L, Inner> {
     #[allow(unused)]
-    pub fn iter(&self) -> Iter<Inner> {
+    pub fn iter(&self) -> Iter<'_, Inner> {
         self.inner.iter()
     }
     #[allow(unused)]
-    pub fn iter_mut(&mut self) -> IterMut<Inner> {
+    pub fn iter_mut(&mut self) -> IterMut<'_, Inner> {
         self.inner.iter_mut()
     }
     #[allow(unused)]
@@ -252,6 +359,82 @@ impl<L, Inner> Array<L, Inner> {
     }
 }
 
+// bytes prefixed with the length of `L`. unlike `Array<L, u8>`, decoding from a packet slices it instead of copying.
+#[derive(PartialEq, Eq, Clone, Hash)]
+pub struct ByteArray<L> {
+    pub bytes: Bytes,
+    pub _phantom: PhantomData<fn(L) -> ()>,
+}
+impl<L> Encodable for ByteArray<L>
+where
+    L: Encodable + ArrayLength,
+{
+    fn encode<T: Write>(&self, writer: &mut T) -> Result<usize> {
+        let len = self.bytes.len();
+        let written = L::from(len, len).encode(writer)?;
+        writer
+            .write_all(&self.bytes)
+            .context("could not write byte array")?;
+        Ok(written + len)
+    }
+    fn encoded_len(&self) -> usize {
+        let len = self.bytes.len();
+        L::from(len, len).encoded_len() + len
+    }
+}
+impl<L> Decodable for ByteArray<L>
+where
+    L: Decodable + ArrayLength,
+{
+    fn decode<T: Read>(reader: &mut T) -> Result<Self> {
+        Ok(Array::<L, u8>::decode_vec(reader)?.into())
+    }
+    fn decode_bytes(buf: &mut Bytes) -> Result<Self> {
+        let length: L = L::decode_bytes(buf).context("Failed to decode byte array length")?;
+        let len = match length.bound()? {
+            ArrayBound::Elements(len) | ArrayBound::Bytes(len) => len,
+            ArrayBound::Rest => buf.len(),
+        };
+        ensure!(
+            len <= buf.len(),
+            "Byte array is truncated: {} byte(s) left of {}",
+            buf.len(),
+            len
+        );
+        Ok(buf.split_to(len).into())
+    }
+}
+impl<L> std::fmt::Debug for ByteArray<L> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct(&format!("ByteArray<{}>", std::any::type_name::<L>()))
+            .field("bytes", &self.bytes)
+            .finish()
+    }
+}
+impl<L> Default for ByteArray<L> {
+    fn default() -> Self {
+        Bytes::new().into()
+    }
+}
+impl<L> From<Bytes> for ByteArray<L> {
+    fn from(bytes: Bytes) -> Self {
+        Self {
+            bytes,
+            _phantom: PhantomData,
+        }
+    }
+}
+impl<L> From<Vec<u8>> for ByteArray<L> {
+    fn from(value: Vec<u8>) -> Self {
+        Bytes::from(value).into()
+    }
+}
+impl<L> From<&[u8]> for ByteArray<L> {
+    fn from(value: &[u8]) -> Self {
+        Bytes::copy_from_slice(value).into()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -260,7 +443,9 @@ mod tests {
     #[test]
     fn array_varint_encode() {
         let mut buf = Vec::new();
-        Array::<VarIntLength, u8>::from(vec![1, 2, 3, 4, 5]).encode(&mut buf);
+        Array::<VarIntLength, u8>::from(vec![1, 2, 3, 4, 5])
+            .encode(&mut buf)
+            .unwrap();
 
         assert_eq!(buf, vec![5, 1, 2, 3, 4, 5]);
     }
@@ -293,7 +478,8 @@ mod tests {
             TestStruct { a: 1, b: 2 },
             TestStruct { a: 3, b: 4 },
         ])
-        .encode(&mut buf);
+        .encode(&mut buf)
+        .unwrap();
 
         assert_eq!(buf, vec![4, 1, 2, 3, 4]);
     }
@@ -331,7 +517,9 @@ mod tests {
     #[test]
     fn array_fixed_encode() {
         let mut buf = Vec::new();
-        Array::<FixedLength<5>, u8>::from(vec![1, 2, 3, 4, 5]).encode(&mut buf);
+        Array::<FixedLength<5>, u8>::from(vec![1, 2, 3, 4, 5])
+            .encode(&mut buf)
+            .unwrap();
 
         assert_eq!(buf, vec![1, 2, 3, 4, 5]);
     }
@@ -354,7 +542,9 @@ mod tests {
     #[test]
     fn array_packet_inferred_encode() {
         let mut buf = Vec::new();
-        Array::<PacketInferredInBytes, u8>::from(vec![1, 2, 3, 4, 5]).encode(&mut buf);
+        Array::<PacketInferredInBytes, u8>::from(vec![1, 2, 3, 4, 5])
+            .encode(&mut buf)
+            .unwrap();
 
         assert_eq!(buf, vec![1, 2, 3, 4, 5]);
     }
@@ -366,4 +556,111 @@ mod tests {
 
         assert_eq!(decoded.inner, vec![1, 2, 3, 4, 5]);
     }
+
+    #[test]
+    fn array_decode_empty() {
+        let mut buf: &[u8] = &[0, 0, 42];
+        assert!(Array::<VarIntLength, u8>::decode(&mut buf)
+            .unwrap()
+            .inner
+            .is_empty());
+        assert!(Array::<VarIntLengthInBytes, u8>::decode(&mut buf)
+            .unwrap()
+            .inner
+            .is_empty());
+        assert_eq!(buf, [42]);
+
+        let mut buf: &[u8] = &[];
+        assert!(Array::<PacketInferredInBytes, u8>::decode(&mut buf)
+            .unwrap()
+            .inner
+            .is_empty());
+    }
+
+    #[test]
+    fn array_decode_stops_at_length() {
+        // the bytes after the array belong to the next field.
+        let mut buf: &[u8] = &[2, 1, 2, 3];
+        let decoded = Array::<VarIntLength, u8>::decode(&mut buf).unwrap();
+        assert_eq!(decoded.inner, vec![1, 2]);
+        assert_eq!(buf, [3]);
+
+        let mut buf: &[u8] = &[3, 0x80, 0x01, 5, 6];
+        let decoded = Array::<VarIntLengthInBytes, VarInt>::decode(&mut buf).unwrap();
+        assert_eq!(decoded.inner, vec![VarInt(128), VarInt(5)]);
+        assert_eq!(buf, [6]);
+    }
+
+    #[test]
+    fn array_decode_reports_element() {
+        // the third element is a truncated VarInt.
+        let mut buf: &[u8] = &[3, 1, 2, 0x80];
+        let e = Array::<VarIntLength, VarInt>::decode(&mut buf).unwrap_err();
+        assert_eq!(e.to_string(), "Failed to decode array element 2");
+
+        let mut buf: &[u8] = &[1, 2, 0x80];
+        let e = Array::<PacketInferredInBytes, VarInt>::decode(&mut buf).unwrap_err();
+        assert_eq!(e.to_string(), "Failed to decode array element 2");
+
+        // the second element crosses the byte length.
+        let mut buf: &[u8] = &[2, 1, 0x80, 0x01];
+        let e = Array::<VarIntLengthInBytes, VarInt>::decode(&mut buf).unwrap_err();
+        assert_eq!(e.to_string(), "Failed to decode array element 1");
+    }
+
+    #[test]
+    fn array_decode_bounded() {
+        let mut buf: &[u8] = &[3, 1, 2, 3];
+        Array::<VarIntLength, u8>::decode_bounded(&mut buf, 2).unwrap_err();
+        // rejected from the prefix, before the elements are read.
+        assert_eq!(buf, [1, 2, 3]);
+
+        let mut buf: &[u8] = &[3, 1, 2, 3];
+        Array::<VarIntLengthInBytes, u8>::decode_bounded(&mut buf, 2).unwrap_err();
+
+        let mut buf: &[u8] = &[0xff, 0xff, 0xff, 0xff, 0x0f];
+        let e = Array::<VarIntLength, u8>::decode(&mut buf).unwrap_err();
+        assert_eq!(e.to_string(), "Invalid array length: -1");
+    }
+
+    #[test]
+    fn array_decode_bytes() {
+        let mut buf = Bytes::from_static(&[3, 0x80, 0x01, 5, 6]);
+        let decoded = Array::<VarIntLengthInBytes, VarInt>::decode_bytes(&mut buf).unwrap();
+        assert_eq!(decoded.inner, vec![VarInt(128), VarInt(5)]);
+        assert_eq!(buf, [6][..]);
+
+        let decoded = Array::<PacketInferredInBytes, u8>::decode_bytes(&mut buf).unwrap();
+        assert_eq!(decoded.inner, vec![6]);
+        assert!(buf.is_empty());
+
+        let mut buf = Bytes::from_static(&[3, 1, 2]);
+        Array::<VarIntLengthInBytes, u8>::decode_bytes(&mut buf).unwrap_err();
+        let mut buf = Bytes::from_static(&[3, 1, 2, 3]);
+        Array::<VarIntLength, u8>::decode_bytes_bounded(&mut buf, 2).unwrap_err();
+    }
+
+    #[test]
+    fn byte_array() {
+        let array = ByteArray::<VarIntLength>::from(vec![1, 2, 3]);
+        let mut buf = Vec::new();
+        array.encode(&mut buf).unwrap();
+        assert_eq!(buf, vec![3, 1, 2, 3]);
+        assert_eq!(array.encoded_len(), buf.len());
+        assert_eq!(
+            ByteArray::<VarIntLength>::decode(&mut buf.as_slice()).unwrap(),
+            array
+        );
+
+        // decoding from a packet shares its memory.
+        buf.push(4);
+        let mut packet = Bytes::from(buf);
+        let decoded = ByteArray::<VarIntLength>::decode_bytes(&mut packet).unwrap();
+        assert_eq!(decoded, array);
+        assert_eq!(decoded.bytes.as_ptr(), packet.as_ptr().wrapping_sub(3));
+        assert_eq!(packet, [4][..]);
+
+        let mut truncated = Bytes::from_static(&[3, 1, 2]);
+        ByteArray::<VarIntLength>::decode_bytes(&mut truncated).unwrap_err();
+    }
 }
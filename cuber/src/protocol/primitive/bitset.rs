@@ -0,0 +1,244 @@
+use std::io::{Read, Write};
+
+use super::{
+    array::{Array, VarIntLength},
+    VarInt,
+};
+use crate::protocol::{Decodable, Encodable};
+
+use anyhow::{ensure, Context as _, Result};
+
+// `java.util.BitSet` as longs, prefixed with the number of longs.
+// bit `i` is bit `i % 64` of the `i / 64`th long.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Hash)]
+pub struct BitSet {
+    words: Vec<u64>,
+}
+
+impl BitSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn get(&self, index: usize) -> bool {
+        self.words
+            .get(index / 64)
+            .is_some_and(|word| word & (1 << (index % 64)) != 0)
+    }
+    pub fn set(&mut self, index: usize, value: bool) {
+        let word = index / 64;
+        if value {
+            if self.words.len() <= word {
+                self.words.resize(word + 1, 0);
+            }
+            self.words[word] |= 1 << (index % 64);
+        } else if let Some(w) = self.words.get_mut(word) {
+            *w &= !(1 << (index % 64));
+            // trailing zero words are not sent, as java does.
+            while self.words.last() == Some(&0) {
+                self.words.pop();
+            }
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+    // indices of the set bits, in ascending order.
+    pub fn ones(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.words.len() * 64).filter(|&i| self.get(i))
+    }
+    pub fn words(&self) -> &[u64] {
+        &self.words
+    }
+}
+
+impl Encodable for BitSet {
+    fn encode<T: Write>(&self, writer: &mut T) -> Result<usize> {
+        let words: Vec<i64> = self.words.iter().map(|&w| w as i64).collect();
+        Array::<VarIntLength, i64>::encode_slice(&words, writer).context("could not write bit set")
+    }
+    fn encoded_len(&self) -> usize {
+        VarInt(self.words.len() as i32).encoded_len() + self.words.len() * 8
+    }
+}
+impl Decodable for BitSet {
+    fn decode<T: Read>(reader: &mut T) -> Result<Self> {
+        let words =
+            Array::<VarIntLength, i64>::decode_vec(reader).context("could not read bit set")?;
+        Ok(words
+            .into_iter()
+            .map(|w| w as u64)
+            .collect::<Vec<_>>()
+            .into())
+    }
+}
+impl From<Vec<u64>> for BitSet {
+    fn from(mut words: Vec<u64>) -> Self {
+        while words.last() == Some(&0) {
+            words.pop();
+        }
+        Self { words }
+    }
+}
+impl From<BitSet> for Vec<u64> {
+    fn from(value: BitSet) -> Self {
+        value.words
+    }
+}
+impl FromIterator<usize> for BitSet {
+    fn from_iter<I: IntoIterator<Item = usize>>(iter: I) -> Self {
+        let mut set = Self::new();
+        for index in iter {
+            set.set(index, true);
+        }
+        set
+    }
+}
+
+// a bit set of `N` bits, as `ceil(N / 8)` bytes without a length prefix.
+// bit `i` is bit `i % 8` of the `i / 8`th byte.
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub struct FixedBitSet<const N: usize> {
+    bytes: Vec<u8>,
+}
+
+impl<const N: usize> Default for FixedBitSet<N> {
+    fn default() -> Self {
+        Self {
+            bytes: vec![0; Self::BYTES],
+        }
+    }
+}
+
+impl<const N: usize> FixedBitSet<N> {
+    const BYTES: usize = N.div_ceil(8);
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn get(&self, index: usize) -> bool {
+        assert!(index < N, "bit index out of range: {} (len {})", index, N);
+        self.bytes[index / 8] & (1 << (index % 8)) != 0
+    }
+    pub fn set(&mut self, index: usize, value: bool) {
+        assert!(index < N, "bit index out of range: {} (len {})", index, N);
+        if value {
+            self.bytes[index / 8] |= 1 << (index % 8);
+        } else {
+            self.bytes[index / 8] &= !(1 << (index % 8));
+        }
+    }
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl<const N: usize> Encodable for FixedBitSet<N> {
+    fn encode<T: Write>(&self, writer: &mut T) -> Result<usize> {
+        writer
+            .write_all(&self.bytes)
+            .context("could not write fixed bit set")?;
+        Ok(self.bytes.len())
+    }
+    fn encoded_len(&self) -> usize {
+        Self::BYTES
+    }
+}
+impl<const N: usize> Decodable for FixedBitSet<N> {
+    fn decode<T: Read>(reader: &mut T) -> Result<Self> {
+        let mut bytes = vec![0; Self::BYTES];
+        reader
+            .read_exact(&mut bytes)
+            .context("could not read fixed bit set")?;
+        Ok(Self { bytes })
+    }
+}
+impl<const N: usize> From<[bool; N]> for FixedBitSet<N> {
+    fn from(value: [bool; N]) -> Self {
+        let mut set = Self::new();
+        for (index, bit) in value.into_iter().enumerate() {
+            set.set(index, bit);
+        }
+        set
+    }
+}
+impl<const N: usize> TryFrom<Vec<u8>> for FixedBitSet<N> {
+    type Error = anyhow::Error;
+
+    fn try_from(bytes: Vec<u8>) -> Result<Self> {
+        ensure!(
+            bytes.len() == Self::BYTES,
+            "Fixed bit set length mismatch: expected {} byte(s), but got {}",
+            Self::BYTES,
+            bytes.len()
+        );
+        Ok(Self { bytes })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    macro_rules! make_bit_set_test_case {
+        ($name: ident, $bytes: expr, $ones: expr) => {
+            #[test]
+            fn $name() {
+                let set: BitSet = $ones.into_iter().collect();
+                let mut buf = Vec::new();
+                set.encode(&mut buf).unwrap();
+                assert_eq!(buf, $bytes);
+                assert_eq!(set.encoded_len(), buf.len());
+
+                let decoded = BitSet::decode(&mut buf.as_slice()).unwrap();
+                assert_eq!(decoded.ones().collect::<Vec<_>>(), $ones);
+            }
+        };
+    }
+
+    make_bit_set_test_case!(test_bit_set_empty, vec![0], Vec::<usize>::new());
+    make_bit_set_test_case!(test_bit_set_zero, vec![1, 0, 0, 0, 0, 0, 0, 0, 1], vec![0]);
+    make_bit_set_test_case!(
+        test_bit_set_63,
+        vec![1, 0x80, 0, 0, 0, 0, 0, 0, 0],
+        vec![63]
+    );
+    make_bit_set_test_case!(
+        test_bit_set_1_and_64,
+        vec![2, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 1],
+        vec![1, 64]
+    );
+
+    #[test]
+    fn bit_set_clear_trims() {
+        let mut set: BitSet = [3, 70].into_iter().collect();
+        set.set(70, false);
+        assert_eq!(set.words(), [0b1000]);
+        set.set(3, false);
+        assert!(set.is_empty());
+        assert!(!set.get(1000));
+    }
+
+    #[test]
+    fn fixed_bit_set() {
+        let mut set = FixedBitSet::<12>::new();
+        set.set(0, true);
+        set.set(9, true);
+        assert!(set.get(9));
+        assert!(!set.get(8));
+
+        let mut buf = Vec::new();
+        set.encode(&mut buf).unwrap();
+        assert_eq!(buf, vec![0b0000_0001, 0b0000_0010]);
+        assert_eq!(set.encoded_len(), 2);
+        assert_eq!(FixedBitSet::<12>::decode(&mut buf.as_slice()).unwrap(), set);
+
+        FixedBitSet::<12>::decode(&mut [0_u8].as_slice()).unwrap_err();
+        FixedBitSet::<12>::try_from(vec![0; 3]).unwrap_err();
+    }
+
+    #[test]
+    #[should_panic]
+    fn fixed_bit_set_out_of_range() {
+        FixedBitSet::<12>::new().set(12, true);
+    }
+}
@@ -6,7 +6,7 @@ use byteorder::ReadBytesExt;
 const VARINT_SEGMENT_BITS: u8 = 0x7f;
 const VARINT_CONTINUE_BIT: u8 = 0x80;
 
-use anyhow::{Context as _, Result, ensure};
+use anyhow::{ensure, Context as _, Result};
 
 pub async fn async_read_var_int<T: AsyncReadExt + Unpin>(d: &mut T) -> Result<(usize, i32)> {
     let mut value = 0;
@@ -54,6 +54,60 @@ pub fn read_var_int<T: ReadBytesExt>(d: &mut T) -> Result<(usize, i32)> {
     Ok((read, value))
 }
 
+pub fn var_int_len(value: i32) -> usize {
+    match value as u32 {
+        0..=0x7f => 1,
+        0x80..=0x3fff => 2,
+        0x4000..=0x1f_ffff => 3,
+        0x20_0000..=0xfff_ffff => 4,
+        _ => 5,
+    }
+}
+
+pub fn read_var_long<T: ReadBytesExt>(d: &mut T) -> Result<(usize, i64)> {
+    let mut value = 0;
+    let mut position = 0;
+    let mut read = 0;
+
+    loop {
+        let current_byte = d.read_u8().context("Failed to read byte")?;
+        read += 1;
+
+        let segment = current_byte & VARINT_SEGMENT_BITS;
+        value |= (segment as i64) << position;
+
+        if current_byte & VARINT_CONTINUE_BIT == 0 {
+            break;
+        }
+
+        position += 7;
+        ensure!(position < 64, "VarLong is too big");
+    }
+
+    Ok((read, value))
+}
+
+pub fn var_long_len(value: i64) -> usize {
+    // 7 bits per byte, and at least one byte for zero.
+    let bits = 64 - (value as u64).leading_zeros() as usize;
+    bits.max(1).div_ceil(7)
+}
+
+pub fn build_var_long(value: i64) -> Vec<u8> {
+    let mut result: Vec<u8> = Vec::new();
+
+    let mut remaining_value = value as u64;
+
+    while remaining_value >= 0x80 {
+        result.push((remaining_value & 0x7F | 0x80) as u8);
+        remaining_value >>= 7;
+    }
+
+    result.push(remaining_value as u8);
+
+    result
+}
+
 pub fn build_var_int(value: i32) -> Vec<u8> {
     let mut result: Vec<u8> = Vec::new();
 
@@ -79,6 +133,7 @@ mod tests {
                 let value = $value;
                 let result = build_var_int(value);
                 assert_eq!(result, $bytes);
+                assert_eq!(var_int_len(value), result.len());
             }
         };
     }
@@ -98,6 +153,33 @@ mod tests {
             }
         };
     }
+    macro_rules! make_build_long_test_case {
+        ($name: ident, $bytes: expr, $value: expr) => {
+            #[test]
+            fn $name() {
+                let value = $value;
+                let result = build_var_long(value);
+                assert_eq!(result, $bytes);
+                assert_eq!(var_long_len(value), result.len());
+            }
+        };
+    }
+
+    macro_rules! make_read_long_test_case {
+        ($name: ident, $bytes: expr, $value: expr) => {
+            #[test]
+            fn $name() {
+                use std::io::Cursor;
+
+                let bytes = $bytes;
+                let length = bytes.len();
+                let mut bytes = Cursor::new(bytes);
+                let (len, v) = read_var_long(&mut bytes).unwrap();
+                assert_eq!(v, $value);
+                assert_eq!(length, len);
+            }
+        };
+    }
     make_build_test_case!(test_build_var_int_zero, vec![0], 0);
     make_build_test_case!(test_build_var_int_one, vec![1], 1);
     make_build_test_case!(test_build_var_int_two, vec![2], 2);
@@ -145,4 +227,74 @@ mod tests {
         vec![0x80, 0x80, 0x80, 0x80, 0x08],
         -2147483648
     );
+
+    make_build_long_test_case!(test_build_var_long_zero, vec![0], 0);
+    make_build_long_test_case!(test_build_var_long_one, vec![1], 1);
+    make_build_long_test_case!(test_build_var_long_two, vec![2], 2);
+    make_build_long_test_case!(test_build_var_long_127, vec![0x7f], 127);
+    make_build_long_test_case!(test_build_var_long_128, vec![0x80, 0x01], 128);
+    make_build_long_test_case!(test_build_var_long_255, vec![0xff, 0x01], 255);
+    make_build_long_test_case!(
+        test_build_var_long_2147483647,
+        vec![0xff, 0xff, 0xff, 0xff, 0x07],
+        2147483647
+    );
+    make_build_long_test_case!(
+        test_build_var_long_9223372036854775807,
+        vec![0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f],
+        9223372036854775807
+    );
+    make_build_long_test_case!(
+        test_build_var_long_minus_1,
+        vec![0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01],
+        -1
+    );
+    make_build_long_test_case!(
+        test_build_var_long_minus_2147483648,
+        vec![0x80, 0x80, 0x80, 0x80, 0xf8, 0xff, 0xff, 0xff, 0xff, 0x01],
+        -2147483648
+    );
+    make_build_long_test_case!(
+        test_build_var_long_minus_9223372036854775808,
+        vec![0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x01],
+        -9223372036854775808
+    );
+
+    make_read_long_test_case!(test_read_var_long_zero, vec![0], 0);
+    make_read_long_test_case!(test_read_var_long_one, vec![1], 1);
+    make_read_long_test_case!(test_read_var_long_two, vec![2], 2);
+    make_read_long_test_case!(test_read_var_long_127, vec![0x7f], 127);
+    make_read_long_test_case!(test_read_var_long_128, vec![0x80, 0x01], 128);
+    make_read_long_test_case!(test_read_var_long_255, vec![0xff, 0x01], 255);
+    make_read_long_test_case!(
+        test_read_var_long_2147483647,
+        vec![0xff, 0xff, 0xff, 0xff, 0x07],
+        2147483647
+    );
+    make_read_long_test_case!(
+        test_read_var_long_9223372036854775807,
+        vec![0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f],
+        9223372036854775807
+    );
+    make_read_long_test_case!(
+        test_read_var_long_minus_1,
+        vec![0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01],
+        -1
+    );
+    make_read_long_test_case!(
+        test_read_var_long_minus_2147483648,
+        vec![0x80, 0x80, 0x80, 0x80, 0xf8, 0xff, 0xff, 0xff, 0xff, 0x01],
+        -2147483648
+    );
+    make_read_long_test_case!(
+        test_read_var_long_minus_9223372036854775808,
+        vec![0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x01],
+        -9223372036854775808
+    );
+
+    #[test]
+    fn test_read_var_long_too_big() {
+        let mut bytes: &[u8] = &[0xff; 11];
+        read_var_long(&mut bytes).unwrap_err();
+    }
 }
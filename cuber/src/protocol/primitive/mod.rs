@@ -1,35 +1,37 @@
 pub mod array;
+pub mod bitset;
 pub mod leb128;
 
-use array::Array;
-use leb128::read_var_int;
+use leb128::{read_var_int, read_var_long};
 
-use self::array::VarIntLength;
-
-use super::{Decodable, Encodable};
+use super::{
+    chat::TextComponent, version::ProtocolVersion, BoundedDecodable, Decodable, Encodable,
+};
 
 use deriver::{Decodable, Encodable};
 
 use byteorder::{NetworkEndian, ReadBytesExt, WriteBytesExt};
+use bytes::Bytes;
 use nbt::Blob;
+use serde::{Deserialize, Serialize};
 use std::io::{Read, Write};
 use uuid::Uuid;
 
-use anyhow::{Result, Context as _, bail, ensure};
+use anyhow::{bail, ensure, Context as _, Result};
 
 macro_rules! write_primitive {
     ($writer: ident, write_u8, $value: expr) => {{
         let bind = $value;
         $writer
             .write_u8(bind)
-            .expect(&format!("could not write data. {}({})", "write_u8", &bind));
+            .with_context(|| format!("could not write data. {}({})", "write_u8", &bind))?;
         1
     }};
     ($writer: ident, write_i8, $value: expr) => {{
         let bind = $value;
         $writer
             .write_i8(bind)
-            .expect(&format!("could not write data. {}({})", "write_i8", &bind));
+            .with_context(|| format!("could not write data. {}({})", "write_i8", &bind))?;
         1
     }};
     ($writer: ident, $method: ident, $value: expr) => {{
@@ -39,11 +41,9 @@ macro_rules! write_primitive {
 
         let bind = $value;
         let len = get_size(&bind);
-        $writer.$method::<NetworkEndian>(bind).expect(&format!(
-            "could not write data. {}({})",
-            stringify!($method),
-            &bind
-        ));
+        $writer
+            .$method::<NetworkEndian>(bind)
+            .with_context(|| format!("could not write data. {}({})", stringify!($method), &bind))?;
 
         len
     }};
@@ -64,15 +64,17 @@ macro_rules! read_primitive {
 macro_rules! define_prim {
     ($type: ty, $write_method: ident, $read_method: ident) => {
         impl Encodable for $type {
-            fn encode<T: Write>(&self, writer: &mut T) -> usize {
-                write_primitive!(writer, $write_method, *self)
+            fn encode<T: Write>(&self, writer: &mut T) -> Result<usize> {
+                Ok(write_primitive!(writer, $write_method, *self))
+            }
+            fn encoded_len(&self) -> usize {
+                std::mem::size_of::<$type>()
             }
         }
         impl Decodable for $type {
             fn decode<T: Read>(reader: &mut T) -> Result<Self> {
-                Ok(read_primitive!(reader, $read_method).with_context(|| {
-                    format!("could not read data. {}()", stringify!($read_method))
-                })?)
+                read_primitive!(reader, $read_method)
+                    .with_context(|| format!("could not read data. {}()", stringify!($read_method)))
             }
         }
     };
@@ -92,9 +94,12 @@ define_prim!(f32, write_f32, read_f32);
 define_prim!(f64, write_f64, read_f64);
 
 impl Encodable for bool {
-    fn encode<T: Write>(&self, writer: &mut T) -> usize {
+    fn encode<T: Write>(&self, writer: &mut T) -> Result<usize> {
         (if *self { 1_u8 } else { 0_u8 }).encode(writer)
     }
+    fn encoded_len(&self) -> usize {
+        1
+    }
 }
 impl Decodable for bool {
     fn decode<T: Read>(reader: &mut T) -> Result<Self> {
@@ -115,16 +120,23 @@ pub struct Angle {
 pub struct VarInt(pub i32);
 
 impl Encodable for VarInt {
-    fn encode<T: Write>(&self, writer: &mut T) -> usize {
+    fn encode<T: Write>(&self, writer: &mut T) -> Result<usize> {
         let bytes = leb128::build_var_int(self.0);
-        writer.write_all(&bytes).expect("could not write all bytes");
+        writer
+            .write_all(&bytes)
+            .context("could not write var int")?;
 
-        bytes.len()
+        Ok(bytes.len())
+    }
+    fn encoded_len(&self) -> usize {
+        leb128::var_int_len(self.0)
     }
 }
 impl Decodable for VarInt {
     fn decode<T: Read>(reader: &mut T) -> Result<Self> {
-        Ok(VarInt(read_var_int(reader).context("could not read var int")?.1))
+        Ok(VarInt(
+            read_var_int(reader).context("could not read var int")?.1,
+        ))
     }
 }
 impl From<i32> for VarInt {
@@ -138,35 +150,217 @@ impl From<VarInt> for i32 {
     }
 }
 
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct VarLong(pub i64);
+
+impl Encodable for VarLong {
+    fn encode<T: Write>(&self, writer: &mut T) -> Result<usize> {
+        let bytes = leb128::build_var_long(self.0);
+        writer
+            .write_all(&bytes)
+            .context("could not write var long")?;
+
+        Ok(bytes.len())
+    }
+    fn encoded_len(&self) -> usize {
+        leb128::var_long_len(self.0)
+    }
+}
+impl Decodable for VarLong {
+    fn decode<T: Read>(reader: &mut T) -> Result<Self> {
+        Ok(VarLong(
+            read_var_long(reader).context("could not read var long")?.1,
+        ))
+    }
+}
+impl From<i64> for VarLong {
+    fn from(value: i64) -> Self {
+        Self(value)
+    }
+}
+impl From<VarLong> for i64 {
+    fn from(value: VarLong) -> Self {
+        value.0
+    }
+}
+
 impl Encodable for String {
-    fn encode<T: Write>(&self, writer: &mut T) -> usize {
+    fn encode<T: Write>(&self, writer: &mut T) -> Result<usize> {
         let mut written = 0;
 
-        written += VarInt(self.len() as _).encode(writer);
+        written += VarInt(self.len() as _).encode(writer)?;
         writer
             .write_all(self.as_bytes())
-            .expect("could not write to buffer");
+            .context("could not write string")?;
         written += self.len();
 
-        written
+        Ok(written)
+    }
+    fn encoded_len(&self) -> usize {
+        VarInt(self.len() as _).encoded_len() + self.len()
     }
 }
+pub const MAX_STRING_LENGTH: usize = 32767;
+
 impl Decodable for String {
     fn decode<T: Read>(reader: &mut T) -> Result<Self> {
-        let buf = Array::<VarIntLength, u8>::decode(reader).context("could not read string length")?.inner;
-        Ok(String::from_utf8(buf)?)
+        Self::decode_bounded(reader, MAX_STRING_LENGTH)
+    }
+    fn decode_bytes(buf: &mut Bytes) -> Result<Self> {
+        Self::decode_bytes_bounded(buf, MAX_STRING_LENGTH)
     }
 }
+impl BoundedDecodable for String {
+    fn decode_bounded<T: Read>(reader: &mut T, max_len: usize) -> Result<Self> {
+        let len = VarInt::decode(reader).context("could not read string length")?;
+        let len = checked_string_len(len, max_len)?;
 
-#[derive(Encodable, Decodable, Debug, PartialEq, Eq, Clone, Hash)]
+        let mut buf = Vec::with_capacity(len);
+        reader
+            .take(len as u64)
+            .read_to_end(&mut buf)
+            .context("could not read string")?;
+        ensure!(buf.len() == len, "string is truncated");
+
+        let s = String::from_utf8(buf)?;
+        check_string_chars(&s, max_len)?;
+        Ok(s)
+    }
+    // validated in place in `buf`, so that the only copy is the one into the string.
+    fn decode_bytes_bounded(buf: &mut Bytes, max_len: usize) -> Result<Self> {
+        let len = VarInt::decode_bytes(buf).context("could not read string length")?;
+        let len = checked_string_len(len, max_len)?;
+        ensure!(len <= buf.len(), "string is truncated");
+
+        let bytes = buf.split_to(len);
+        let s = std::str::from_utf8(&bytes)?;
+        check_string_chars(s, max_len)?;
+        Ok(s.to_owned())
+    }
+}
+// `max_len` counts UTF-16 code units like vanilla, so the byte length is at most `max_len * 3`.
+fn checked_string_len(len: VarInt, max_len: usize) -> Result<usize> {
+    ensure!(len.0 >= 0, "invalid string length: {}", len.0);
+    ensure!(
+        len.0 as usize <= max_len * 3,
+        "string is too long: {} bytes (max {} characters)",
+        len.0,
+        max_len
+    );
+    Ok(len.0 as usize)
+}
+fn check_string_chars(s: &str, max_len: usize) -> Result<()> {
+    let chars = s.encode_utf16().count();
+    ensure!(
+        chars <= max_len,
+        "string is too long: {} characters (max {})",
+        chars,
+        max_len
+    );
+    Ok(())
+}
+
+// sent as JSON text, or as network NBT since 1.20.3.
+// no 1.20.3 packet ids exist yet, so the NBT form is only reached from tests for now.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Hash)]
+#[serde(transparent)]
 pub struct Chat {
-    buf: String,
+    pub component: TextComponent,
+}
+impl Encodable for Chat {
+    fn encode<T: Write>(&self, writer: &mut T) -> Result<usize> {
+        if ProtocolVersion::current() >= ProtocolVersion::V1_20_3 {
+            self.encode_nbt(writer)
+        } else {
+            self.encode_json(writer)
+        }
+    }
+    fn encoded_len(&self) -> usize {
+        if ProtocolVersion::current() >= ProtocolVersion::V1_20_3 {
+            self.encoded_nbt_len()
+        } else {
+            self.encoded_json_len()
+        }
+    }
+}
+impl Decodable for Chat {
+    fn decode<T: Read>(reader: &mut T) -> Result<Self> {
+        Self::decode_bounded(reader, MAX_CHAT_LENGTH)
+    }
+}
+// the default limit of a chat field, which is the general string limit of the protocol.
+// a field with a tighter limit can decode with `#[cuber(max_len = N)]` instead.
+pub const MAX_CHAT_LENGTH: usize = 32767;
+
+impl BoundedDecodable for Chat {
+    fn decode_bounded<T: Read>(reader: &mut T, max_len: usize) -> Result<Self> {
+        if ProtocolVersion::current() >= ProtocolVersion::V1_20_3 {
+            let id = reader.read_u8().context("Failed to read chat tag id")?;
+            let value =
+                nbt::Value::from_reader(id, reader).context("Failed to read chat as NBT")?;
+            return Ok(Self {
+                component: TextComponent::from_nbt(&value)?,
+            });
+        }
+
+        let json = String::decode_bounded(reader, max_len)?;
+        Ok(Self {
+            component: serde_json::from_str(&json).context("Failed to parse chat")?,
+        })
+    }
+}
+impl Chat {
+    pub fn text(text: &str) -> Self {
+        TextComponent::text(text).into()
+    }
+    pub fn encode_json<T: Write>(&self, writer: &mut T) -> Result<usize> {
+        serde_json::to_string(&self.component)
+            .context("Failed to serialize chat")?
+            .encode(writer)
+    }
+    // components are plain data, neither conversion can fail for them.
+    pub fn encoded_json_len(&self) -> usize {
+        serde_json::to_string(&self.component)
+            .expect("text components always serialize")
+            .encoded_len()
+    }
+    pub fn encoded_nbt_len(&self) -> usize {
+        self.component
+            .to_nbt()
+            .expect("text components always convert to NBT")
+            .len_bytes()
+    }
+    pub fn encode_nbt<T: Write>(&self, writer: &mut T) -> Result<usize> {
+        let value = self.component.to_nbt()?;
+        writer.write_u8(value.id())?;
+        value
+            .to_writer(writer)
+            .context("Failed to write chat as NBT")?;
+        Ok(value.len_bytes())
+    }
+}
+impl From<TextComponent> for Chat {
+    fn from(component: TextComponent) -> Self {
+        Self { component }
+    }
 }
 
 #[derive(Encodable, Decodable, Debug, PartialEq, Eq, Clone, Hash)]
 pub struct Identifier {
     buf: String,
 }
+impl BoundedDecodable for Identifier {
+    fn decode_bounded<T: Read>(reader: &mut T, max_len: usize) -> Result<Self> {
+        Ok(Self {
+            buf: String::decode_bounded(reader, max_len)?,
+        })
+    }
+    fn decode_bytes_bounded(buf: &mut Bytes, max_len: usize) -> Result<Self> {
+        Ok(Self {
+            buf: String::decode_bytes_bounded(buf, max_len)?,
+        })
+    }
+}
 impl From<String> for Identifier {
     fn from(value: String) -> Self {
         Self { buf: value }
@@ -186,14 +380,19 @@ impl From<Identifier> for String {
 }
 
 impl Encodable for Uuid {
-    fn encode<T: Write>(&self, writer: &mut T) -> usize {
+    fn encode<T: Write>(&self, writer: &mut T) -> Result<usize> {
         self.as_u128().encode(writer)
     }
+    fn encoded_len(&self) -> usize {
+        16
+    }
 }
 
 impl Decodable for Uuid {
     fn decode<T: Read>(reader: &mut T) -> Result<Self> {
-        Ok(Self::from_u128_le(u128::decode(reader).context("could not read uuid")?))
+        Ok(Self::from_u128(
+            u128::decode(reader).context("could not read uuid")?,
+        ))
     }
 }
 
@@ -247,9 +446,12 @@ impl Position {
 }
 
 impl Encodable for Position {
-    fn encode<T: Write>(&self, writer: &mut T) -> usize {
+    fn encode<T: Write>(&self, writer: &mut T) -> Result<usize> {
         self.pack().encode(writer)
     }
+    fn encoded_len(&self) -> usize {
+        8
+    }
 }
 
 impl Decodable for Position {
@@ -259,8 +461,11 @@ impl Decodable for Position {
 }
 
 impl Encodable for Blob {
-    fn encode<T: Write>(&self, writer: &mut T) -> usize {
-        self.to_writer(writer).expect("could not write nbt data");
+    fn encode<T: Write>(&self, writer: &mut T) -> Result<usize> {
+        self.to_writer(writer).context("could not write nbt data")?;
+        Ok(self.len_bytes())
+    }
+    fn encoded_len(&self) -> usize {
         self.len_bytes()
     }
 }
@@ -271,6 +476,69 @@ impl Decodable for Blob {
     }
 }
 
+// the root compound has a name up to 1.20.1, and is nameless since 1.20.2.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct NetworkNbt {
+    pub blob: Blob,
+}
+impl Encodable for NetworkNbt {
+    fn encode<T: Write>(&self, writer: &mut T) -> Result<usize> {
+        if ProtocolVersion::current() < ProtocolVersion::V1_20_2 {
+            return self.blob.encode(writer);
+        }
+
+        let mut buf = Vec::with_capacity(self.blob.len_bytes());
+        self.blob.encode(&mut buf)?;
+
+        // drop the name, which follows the tag id as a length prefixed string.
+        let name_len = u16::from_be_bytes([buf[1], buf[2]]) as usize;
+        buf.drain(1..3 + name_len);
+
+        writer.write_all(&buf).context("could not write nbt data")?;
+        Ok(buf.len())
+    }
+    fn encoded_len(&self) -> usize {
+        let len = self.blob.len_bytes();
+        if ProtocolVersion::current() < ProtocolVersion::V1_20_2 {
+            return len;
+        }
+
+        // the tag id and the length of the name come first, writing stops when the 3 bytes are full.
+        let mut header = [0; 3];
+        let _ = self.blob.to_writer(&mut &mut header[..]);
+        let name_len = u16::from_be_bytes([header[1], header[2]]) as usize;
+        len - 2 - name_len
+    }
+}
+impl Decodable for NetworkNbt {
+    fn decode<T: Read>(reader: &mut T) -> Result<Self> {
+        if ProtocolVersion::current() < ProtocolVersion::V1_20_2 {
+            return Ok(Self {
+                blob: Blob::decode(reader)?,
+            });
+        }
+
+        let id = reader.read_u8().context("could not read nbt tag id")?;
+        ensure!(id == 0x0a, "root tag is not a compound: {:#04x}", id);
+
+        // give it back an empty name, so that the named form can be read.
+        let mut named = std::io::Cursor::new([0x0a, 0x00, 0x00]).chain(reader);
+        Ok(Self {
+            blob: Blob::from_reader(&mut named)?,
+        })
+    }
+}
+impl From<Blob> for NetworkNbt {
+    fn from(blob: Blob) -> Self {
+        Self { blob }
+    }
+}
+impl From<NetworkNbt> for Blob {
+    fn from(value: NetworkNbt) -> Self {
+        value.blob
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Hash)]
 pub struct BoolConditional<T>(pub Option<T>);
 
@@ -278,18 +546,21 @@ impl<Inner> Encodable for BoolConditional<Inner>
 where
     Inner: Encodable,
 {
-    fn encode<T: Write>(&self, writer: &mut T) -> usize {
+    fn encode<T: Write>(&self, writer: &mut T) -> Result<usize> {
         match &self.0 {
             Some(obj) => {
                 let mut written = 0;
-                written += true.encode(writer);
-                written += obj.encode(writer);
+                written += true.encode(writer)?;
+                written += obj.encode(writer)?;
 
-                return written;
+                Ok(written)
             }
             None => false.encode(writer),
         }
     }
+    fn encoded_len(&self) -> usize {
+        1 + self.0.as_ref().map_or(0, Encodable::encoded_len)
+    }
 }
 
 impl<Inner> Decodable for BoolConditional<Inner>
@@ -300,7 +571,30 @@ where
         if !bool::decode(reader).context("could not read bool in BoolConditional")? {
             return Ok(Self(None));
         }
-        return Ok(Self(Some(Inner::decode(reader).context("could not read inner value in BoolConditional")?)));
+        Ok(Self(Some(Inner::decode(reader).context(
+            "could not read inner value in BoolConditional",
+        )?)))
+    }
+    fn decode_bytes(buf: &mut Bytes) -> Result<Self> {
+        if !bool::decode_bytes(buf).context("could not read bool in BoolConditional")? {
+            return Ok(Self(None));
+        }
+        Ok(Self(Some(Inner::decode_bytes(buf).context(
+            "could not read inner value in BoolConditional",
+        )?)))
+    }
+}
+impl<Inner> BoundedDecodable for BoolConditional<Inner>
+where
+    Inner: BoundedDecodable,
+{
+    fn decode_bounded<T: Read>(reader: &mut T, max_len: usize) -> Result<Self> {
+        if !bool::decode(reader).context("could not read bool in BoolConditional")? {
+            return Ok(Self(None));
+        }
+        Ok(Self(Some(Inner::decode_bounded(reader, max_len).context(
+            "could not read inner value in BoolConditional",
+        )?)))
     }
 }
 impl<Inner> From<Option<Inner>> for BoolConditional<Inner> {
@@ -314,16 +608,86 @@ impl<Inner> From<BoolConditional<Inner>> for Option<Inner> {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
-pub struct Todo;
-impl Encodable for Todo {
-    fn encode<T: Write>(&self, _writer: &mut T) -> usize {
-        todo!()
+// present if anything of the packet remains, so it can only be the last field.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Hash)]
+pub struct Optional<T>(pub Option<T>);
+
+impl<Inner> Encodable for Optional<Inner>
+where
+    Inner: Encodable,
+{
+    fn encode<T: Write>(&self, writer: &mut T) -> Result<usize> {
+        match &self.0 {
+            Some(obj) => obj.encode(writer),
+            None => Ok(0),
+        }
+    }
+    fn encoded_len(&self) -> usize {
+        self.0.as_ref().map_or(0, Encodable::encoded_len)
+    }
+}
+
+impl<Inner> Decodable for Optional<Inner>
+where
+    Inner: Decodable,
+{
+    fn decode<T: Read>(reader: &mut T) -> Result<Self> {
+        // a reader can not be peeked, so the first byte is read and put back in front.
+        let mut first = [0];
+        if reader
+            .read(&mut first)
+            .context("could not read optional value")?
+            == 0
+        {
+            return Ok(Self(None));
+        }
+        let mut reader = first.as_slice().chain(reader);
+        Ok(Self(Some(
+            Inner::decode(&mut reader).context("could not read inner value in Optional")?,
+        )))
+    }
+    fn decode_bytes(buf: &mut Bytes) -> Result<Self> {
+        if buf.is_empty() {
+            return Ok(Self(None));
+        }
+        Ok(Self(Some(
+            Inner::decode_bytes(buf).context("could not read inner value in Optional")?,
+        )))
+    }
+}
+impl<Inner> From<Option<Inner>> for Optional<Inner> {
+    fn from(value: Option<Inner>) -> Self {
+        Self(value)
+    }
+}
+impl<Inner> From<Optional<Inner>> for Option<Inner> {
+    fn from(value: Optional<Inner>) -> Self {
+        value.0
+    }
+}
+
+// the rest of the packet, e.g. the payload of a plugin message.
+// decoding from a received packet slices its buffer instead of copying.
+impl Encodable for Bytes {
+    fn encode<T: Write>(&self, writer: &mut T) -> Result<usize> {
+        writer.write_all(self).context("could not write bytes")?;
+        Ok(self.len())
+    }
+    fn encoded_len(&self) -> usize {
+        self.len()
     }
 }
-impl Decodable for Todo {
-    fn decode<T: Read>(_reader: &mut T) -> Result<Self> {
-        todo!()
+
+impl Decodable for Bytes {
+    fn decode<T: Read>(reader: &mut T) -> Result<Self> {
+        let mut buf = Vec::new();
+        reader
+            .read_to_end(&mut buf)
+            .context("could not read bytes")?;
+        Ok(buf.into())
+    }
+    fn decode_bytes(buf: &mut Bytes) -> Result<Self> {
+        Ok(std::mem::take(buf))
     }
 }
 
@@ -356,21 +720,28 @@ mod tests {
 
         let buf = Vec::new();
         let mut buf_writer = BufWriter::new(buf);
-        tt.clone().encode(&mut buf_writer);
+        tt.clone().encode(&mut buf_writer).unwrap();
 
-        let mut rp = ReceivedPacket {
-            buf: Cursor::new(buf_writer.into_inner().unwrap().into_boxed_slice()),
-        };
+        let mut rp = ReceivedPacket::new(buf_writer.into_inner().unwrap().into_boxed_slice());
 
         let r = TestType::decode(&mut rp);
 
         assert_eq!(r.unwrap(), tt);
     }
 
+    #[test]
+    fn encode_error() {
+        let mut buf = [0_u8; 3];
+        String::from("hello")
+            .encode(&mut buf.as_mut_slice())
+            .unwrap_err();
+        0_i32.encode(&mut buf.as_mut_slice()).unwrap_err();
+    }
+
     #[test]
     fn f32_encode() {
         let mut buf = Vec::new();
-        0.1_f32.encode(&mut buf);
+        0.1_f32.encode(&mut buf).unwrap();
 
         // 0.1 = 0x3dcccccd
         assert_eq!(buf, vec![0x3d, 0xcc, 0xcc, 0xcd]);
@@ -385,8 +756,8 @@ mod tests {
     #[test]
     fn bool_encode() {
         let mut buf = Vec::new();
-        true.encode(&mut buf);
-        false.encode(&mut buf);
+        true.encode(&mut buf).unwrap();
+        false.encode(&mut buf).unwrap();
 
         assert_eq!(buf, vec![1, 0]);
     }
@@ -394,15 +765,33 @@ mod tests {
     #[test]
     fn bool_decode() {
         let mut buf = Cursor::new(vec![1, 0, 10]);
-        assert_eq!(bool::decode(&mut buf).unwrap(), true);
-        assert_eq!(bool::decode(&mut buf).unwrap(), false);
+        assert!(bool::decode(&mut buf).unwrap());
+        assert!(!bool::decode(&mut buf).unwrap());
         bool::decode(&mut buf).unwrap_err();
     }
 
+    #[test]
+    fn uuid_decode_big_endian() {
+        // most significant byte first, as `UUID` is written by java.
+        let raw: [u8; 16] = [
+            0x06, 0x9a, 0x79, 0xf4, 0x44, 0xe9, 0x47, 0x26, 0xa5, 0xbe, 0xfc, 0xa9, 0x0e, 0x38,
+            0xaa, 0xf5,
+        ];
+        let uuid = Uuid::decode(&mut raw.as_slice()).unwrap();
+        assert_eq!(
+            uuid,
+            Uuid::parse_str("069a79f4-44e9-4726-a5be-fca90e38aaf5").unwrap()
+        );
+
+        let mut buf = Vec::new();
+        uuid.encode(&mut buf).unwrap();
+        assert_eq!(buf, raw);
+    }
+
     #[test]
     fn string_encode() {
         let mut buf = Vec::new();
-        String::from("hello").encode(&mut buf);
+        String::from("hello").encode(&mut buf).unwrap();
 
         assert_eq!(buf, vec![5, 104, 101, 108, 108, 111]);
     }
@@ -416,11 +805,370 @@ mod tests {
         );
     }
 
+    #[test]
+    fn string_decode_bounded() {
+        let hello = vec![5, 104, 101, 108, 108, 111];
+        String::decode_bounded(&mut Cursor::new(hello.clone()), 4).unwrap_err();
+        assert_eq!(
+            String::decode_bounded(&mut Cursor::new(hello), 5).unwrap(),
+            "hello"
+        );
+
+        // 3 bytes in UTF-8 but a single UTF-16 code unit.
+        let a = vec![3, 0xe3, 0x81, 0x82];
+        assert_eq!(
+            String::decode_bounded(&mut Cursor::new(a), 1).unwrap(),
+            "あ"
+        );
+
+        // never trust the length prefix.
+        let huge = vec![0xff, 0xff, 0xff, 0xff, 0x07];
+        String::decode(&mut Cursor::new(huge)).unwrap_err();
+    }
+
+    #[test]
+    fn string_decode_bytes() {
+        let mut buf = Bytes::from_static(&[5, 104, 101, 108, 108, 111, 3, 0xe3, 0x81, 0x82, 2, 0]);
+        String::decode_bytes_bounded(&mut buf.clone(), 4).unwrap_err();
+        assert_eq!(String::decode_bytes(&mut buf).unwrap(), "hello");
+        assert_eq!(String::decode_bytes_bounded(&mut buf, 1).unwrap(), "あ");
+        String::decode_bytes(&mut buf).unwrap_err();
+    }
+
+    #[test]
+    fn derive_max_len() {
+        #[derive(Decodable, Debug)]
+        struct TestType {
+            #[max_len = 3]
+            short: String,
+            long: String,
+        }
+
+        let ok = vec![3, b'a', b'b', b'c', 4, b'd', b'e', b'f', b'g'];
+        let decoded = TestType::decode(&mut Cursor::new(ok)).unwrap();
+        assert_eq!(decoded.short, "abc");
+        assert_eq!(decoded.long, "defg");
+
+        let too_long = vec![4, b'a', b'b', b'c', b'd', 0];
+        TestType::decode(&mut Cursor::new(too_long)).unwrap_err();
+    }
+
+    #[test]
+    fn derive_versions() {
+        #[derive(Encodable, Decodable, Debug, PartialEq)]
+        struct TestType {
+            always: u8,
+            #[cuber(versions = V1_20_1..)]
+            since: u8,
+            #[cuber(versions = ..V1_20_1)]
+            until: u8,
+        }
+
+        let value = TestType {
+            always: 1,
+            since: 2,
+            until: 3,
+        };
+        let encode = |version: ProtocolVersion| {
+            version.scope(|| {
+                let mut buf = vec![];
+                value.encode(&mut buf).unwrap();
+                buf
+            })
+        };
+
+        assert_eq!(encode(ProtocolVersion::V1_19_4), vec![1, 3]);
+        assert_eq!(encode(ProtocolVersion::V1_20_2), vec![1, 2]);
+
+        let decoded = ProtocolVersion::V1_19_4
+            .scope(|| TestType::decode(&mut Cursor::new(vec![1, 3])))
+            .unwrap();
+        assert_eq!(
+            decoded,
+            TestType {
+                always: 1,
+                since: 0,
+                until: 3
+            }
+        );
+    }
+
+    #[test]
+    fn encoded_len_matches_encode() {
+        use super::array::{Array, VarIntLength, VarIntLengthInBytes};
+
+        fn check<E: Encodable>(value: E) {
+            let mut buf = vec![];
+            value.encode(&mut buf).unwrap();
+            assert_eq!(value.encoded_len(), buf.len());
+        }
+
+        check(0x7f_u8);
+        check(1.5_f64);
+        check(true);
+        check(VarInt(-1));
+        check(VarInt(300));
+        check(VarLong(-1));
+        check(VarLong(1 << 40));
+        check("héllo".to_string());
+        check(Uuid::from_u128(1));
+        check(Position::new(1, 2, 3).unwrap());
+        check(BoolConditional(Some(VarInt(128))));
+        check(BoolConditional::<u8>(None));
+        check(Optional(Some(VarInt(128))));
+        check(Optional::<u8>(None));
+        check(bitset::BitSet::from(vec![1, 0, 5]));
+        check(bitset::FixedBitSet::<20>::new());
+        check(Array::<VarIntLength, String>::from(vec![
+            "a".to_string();
+            200
+        ]));
+        check(Array::<VarIntLengthInBytes, VarInt>::from(vec![
+            VarInt(1000);
+            100
+        ]));
+        check(Chat::text("hi"));
+        check(Blob::new());
+    }
+
+    #[test]
+    fn derive_tuple_and_generic() {
+        #[derive(Encodable, Decodable, Debug, PartialEq, Clone)]
+        struct Pair(u8, VarInt);
+
+        #[derive(Encodable, Decodable, Debug, PartialEq)]
+        struct Wrapper<T> {
+            count: u8,
+            inner: T,
+        }
+
+        #[derive(Encodable, Decodable, Debug, PartialEq)]
+        struct Newtype<T>(T)
+        where
+            T: Clone;
+
+        let value = Wrapper {
+            count: 1,
+            inner: Newtype(Pair(2, VarInt(300))),
+        };
+        let mut buf = vec![];
+        assert_eq!(value.encode(&mut buf).unwrap(), 4);
+        assert_eq!(buf, vec![1, 2, 0xac, 0x02]);
+        assert_eq!(
+            Wrapper::<Newtype<Pair>>::decode(&mut Cursor::new(buf)).unwrap(),
+            value
+        );
+    }
+
+    #[test]
+    fn derive_field_attributes() {
+        use super::array::VarIntLength;
+
+        // yaw in degrees, sent as an angle
+        mod degrees {
+            use super::*;
+
+            pub fn encode<W: Write>(value: &f32, writer: &mut W) -> Result<usize> {
+                ((value / 360.0 * 256.0) as u8).encode(writer)
+            }
+            pub fn encoded_len(_value: &f32) -> usize {
+                1
+            }
+            pub fn decode<R: Read>(reader: &mut R) -> Result<f32> {
+                Ok(u8::decode(reader)? as f32 * 360.0 / 256.0)
+            }
+        }
+
+        #[derive(Encodable, Decodable, Debug, PartialEq)]
+        struct TestType {
+            has_name: bool,
+            #[cuber(if = "*has_name")]
+            #[cuber(max_len = 16)]
+            name: Option<String>,
+            kind: u8,
+            #[cuber(if = "*kind == 2", default)]
+            extra: i32,
+            #[cuber(skip)]
+            cache: u32,
+            #[cuber(len = VarIntLength)]
+            values: Vec<u8>,
+            #[cuber(with = "degrees")]
+            yaw: f32,
+        }
+
+        let value = TestType {
+            has_name: true,
+            name: Some("a".into()),
+            kind: 1,
+            extra: 0,
+            cache: 5,
+            values: vec![7, 8],
+            yaw: 90.0,
+        };
+        let mut buf = vec![];
+        assert_eq!(value.encode(&mut buf).unwrap(), 8);
+        assert_eq!(value.encoded_len(), 8);
+        assert_eq!(buf, vec![1, 1, b'a', 1, 2, 7, 8, 64]);
+        assert_eq!(
+            TestType::decode(&mut Cursor::new(buf.clone())).unwrap(),
+            TestType { cache: 0, ..value }
+        );
+        // through `decode_bytes`, which stops at the end of the value as well
+        buf.push(9);
+        let mut bytes = Bytes::from(buf);
+        let decoded = TestType::decode_bytes(&mut bytes).unwrap();
+        assert_eq!(bytes, [9][..]);
+        assert_eq!(decoded.values, vec![7, 8]);
+        assert_eq!(decoded.yaw, 90.0);
+
+        let value = TestType {
+            has_name: false,
+            name: None,
+            kind: 2,
+            extra: 3,
+            cache: 0,
+            values: vec![],
+            yaw: 0.0,
+        };
+        let mut buf = vec![];
+        value.encode(&mut buf).unwrap();
+        assert_eq!(buf, vec![0, 2, 0, 0, 0, 3, 0, 0]);
+        assert_eq!(TestType::decode(&mut Cursor::new(buf)).unwrap(), value);
+
+        let missing = TestType {
+            has_name: true,
+            name: None,
+            ..value
+        };
+        missing.encode(&mut vec![]).unwrap_err();
+    }
+
+    #[test]
+    fn derive_enum() {
+        #[derive(Encodable, Decodable, Debug, PartialEq)]
+        #[repr_kind(varint)]
+        enum Particle {
+            #[value = 2]
+            Block(VarInt),
+            #[value = 14]
+            Dust {
+                red: f32,
+                green: f32,
+                blue: f32,
+                scale: f32,
+            },
+            #[value = 300]
+            Flash,
+            Other(i32),
+        }
+
+        let cases = [
+            (Particle::Block(VarInt(9)), vec![2, 9]),
+            (
+                Particle::Dust {
+                    red: 1.0,
+                    green: 0.0,
+                    blue: 0.0,
+                    scale: 1.0,
+                },
+                vec![14, 63, 128, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 63, 128, 0, 0],
+            ),
+            (Particle::Flash, vec![0xac, 0x02]),
+            (Particle::Other(5), vec![5]),
+        ];
+        for (particle, bytes) in cases {
+            let mut buf = vec![];
+            assert_eq!(particle.encode(&mut buf).unwrap(), bytes.len());
+            assert_eq!(particle.encoded_len(), bytes.len());
+            assert_eq!(buf, bytes);
+            assert_eq!(Particle::decode(&mut Cursor::new(bytes)).unwrap(), particle);
+        }
+
+        #[derive(Encodable, Decodable, Debug, PartialEq)]
+        #[repr_kind(i8)]
+        enum Strict {
+            #[value(-1)]
+            None,
+            #[value = 0]
+            Zero,
+        }
+
+        let mut buf = vec![];
+        Strict::None.encode(&mut buf).unwrap();
+        assert_eq!(buf, vec![0xff]);
+        assert_eq!(
+            Strict::decode(&mut Cursor::new(vec![0])).unwrap(),
+            Strict::Zero
+        );
+        Strict::decode(&mut Cursor::new(vec![1])).unwrap_err();
+
+        #[derive(Encodable, Decodable, Debug, PartialEq)]
+        #[repr_kind(string)]
+        enum Named {
+            #[value = "minecraft:a"]
+            A,
+            Other(Identifier),
+        }
+
+        let mut buf = vec![];
+        Named::A.encode(&mut buf).unwrap();
+        assert_eq!(Named::decode(&mut Cursor::new(buf)).unwrap(), Named::A);
+        let mut buf = vec![];
+        "minecraft:b".to_string().encode(&mut buf).unwrap();
+        assert_eq!(
+            Named::decode(&mut Cursor::new(buf)).unwrap(),
+            Named::Other("minecraft:b".into())
+        );
+    }
+
+    #[test]
+    fn var_long_encode_decode() {
+        let mut buf = Vec::new();
+        VarLong(25565).encode(&mut buf).unwrap();
+        VarLong(i64::MIN).encode(&mut buf).unwrap();
+        assert_eq!(buf[..3], [0xdd, 0xc7, 0x01]);
+
+        let mut buf = Cursor::new(buf);
+        assert_eq!(VarLong::decode(&mut buf).unwrap(), VarLong(25565));
+        assert_eq!(i64::from(VarLong::decode(&mut buf).unwrap()), i64::MIN);
+    }
+
+    #[test]
+    fn optional_inferred_from_remaining() {
+        let mut buf = Vec::new();
+        Optional(Some(VarInt(300))).encode(&mut buf).unwrap();
+        Optional::<VarInt>(None).encode(&mut buf).unwrap();
+        assert_eq!(buf, vec![0xac, 0x02]);
+
+        let mut reader = buf.as_slice();
+        assert_eq!(
+            Optional::decode(&mut reader).unwrap(),
+            Optional(Some(VarInt(300)))
+        );
+        assert_eq!(
+            Optional::<VarInt>::decode(&mut reader).unwrap(),
+            Optional(None)
+        );
+
+        let mut bytes = Bytes::from(buf);
+        assert_eq!(
+            Optional::decode_bytes(&mut bytes).unwrap(),
+            Optional(Some(VarInt(300)))
+        );
+        assert_eq!(
+            Optional::<VarInt>::decode_bytes(&mut bytes).unwrap(),
+            Optional(None)
+        );
+
+        // anything left is the value, and it has to be complete.
+        Optional::<u16>::decode(&mut [1_u8].as_slice()).unwrap_err();
+    }
+
     #[test]
     fn bool_condition_encode() {
         let mut buf = Vec::new();
-        BoolConditional::<u8>(Some(5_u8)).encode(&mut buf);
-        BoolConditional::<u8>(None).encode(&mut buf);
+        BoolConditional::<u8>(Some(5_u8)).encode(&mut buf).unwrap();
+        BoolConditional::<u8>(None).encode(&mut buf).unwrap();
 
         assert_eq!(buf, vec![1, 5, 0]);
     }
@@ -439,6 +1187,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::unusual_byte_groupings)]
     fn position_unpack() {
         let raw = 0b01000110000001110110001100_10110000010101101101001000_001100111111;
         let Position { x, y, z } = Position::unpack(raw).unwrap();
@@ -449,6 +1198,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::unusual_byte_groupings)]
     fn position_pack() {
         let pos = Position::new(18357644, 831, -20882616).unwrap();
         assert_eq!(
@@ -456,4 +1206,82 @@ mod tests {
             0b01000110000001110110001100_10110000010101101101001000_001100111111
         );
     }
+
+    #[test]
+    fn chat_encode_decode() {
+        use crate::protocol::chat::NamedColor;
+
+        let chat = Chat::from(TextComponent::text("bye").color(NamedColor::Red));
+        let mut buf = vec![];
+        chat.encode(&mut buf).unwrap();
+
+        assert_eq!(chat.encoded_len(), buf.len());
+
+        let json = r#"{"text":"bye","color":"red"}"#;
+        assert_eq!(buf[0] as usize, json.len());
+        assert_eq!(&buf[1..], json.as_bytes());
+        assert_eq!(Chat::decode(&mut &buf[..]).unwrap(), chat);
+    }
+
+    #[test]
+    fn chat_encode_decode_nbt() {
+        let chat = Chat::from(TextComponent::text("hi").bold(true));
+
+        ProtocolVersion::V1_20_3.scope(|| {
+            let mut buf = vec![];
+            let written = chat.encode(&mut buf).unwrap();
+            assert_eq!(written, buf.len());
+            assert_eq!(chat.encoded_len(), buf.len());
+
+            let mut reader = &buf[..];
+            let id = reader.read_u8().unwrap();
+            let value = nbt::Value::from_reader(id, &mut reader).unwrap();
+            assert!(reader.is_empty());
+            assert_eq!(
+                value,
+                nbt::Value::Compound(
+                    [
+                        ("text".to_string(), nbt::Value::String("hi".to_string())),
+                        ("bold".to_string(), nbt::Value::Byte(1)),
+                    ]
+                    .into()
+                )
+            );
+
+            assert_eq!(Chat::decode(&mut &buf[..]).unwrap(), chat);
+        });
+    }
+
+    #[test]
+    fn network_nbt() {
+        let mut blob = Blob::named("root");
+        blob.insert("a", 1_i8).unwrap();
+        let nbt = NetworkNbt::from(blob.clone());
+
+        let mut named = vec![];
+        let written = nbt.encode(&mut named).unwrap();
+        assert_eq!(written, named.len());
+        assert_eq!(nbt.encoded_len(), named.len());
+        assert_eq!(
+            named,
+            [0x0a, 0, 4, b'r', b'o', b'o', b't', 0x01, 0, 1, b'a', 1, 0]
+        );
+        assert_eq!(NetworkNbt::decode(&mut &named[..]).unwrap(), nbt);
+
+        ProtocolVersion::V1_20_2.scope(|| {
+            let mut nameless = vec![];
+            let written = nbt.encode(&mut nameless).unwrap();
+            assert_eq!(written, nameless.len());
+            assert_eq!(nbt.encoded_len(), nameless.len());
+            assert_eq!(nameless, [0x0a, 0x01, 0, 1, b'a', 1, 0]);
+
+            // the name does not survive the round trip.
+            let mut expected = Blob::new();
+            expected.insert("a", 1_i8).unwrap();
+            assert_eq!(
+                NetworkNbt::decode(&mut &nameless[..]).unwrap(),
+                NetworkNbt::from(expected)
+            );
+        });
+    }
 }
@@ -1,24 +1,50 @@
 use super::primitive::{
-    array::{Array, PacketInferredInBytes, VarIntLength},
+    array::{ByteArray, VarIntLength},
     Identifier,
 };
-use super::{primitive::BoolConditional, primitive::VarInt, Decodable};
+use super::{
+    common::{ConnectionState, State},
+    primitive::BoolConditional,
+    primitive::VarInt,
+    version::ProtocolVersion,
+    BoundedDecodable, Decodable, ReceivedPacket,
+};
+use bytes::Bytes;
 use deriver::Decodable;
+use md5::{Digest, Md5};
 use packet_id::sb_packet;
 use std::io::Read;
 use uuid::Uuid;
 
-use anyhow::{Result, Context as _, bail};
+use anyhow::{bail, Context as _, Result};
 
 pub trait ServerBoundPacket: Decodable {
-    const PACKET_ID: i32;
+    // `None` if the packet does not exist in the version.
+    fn packet_id(version: ProtocolVersion) -> Option<i32>;
 }
 
 pub trait PacketCluster: Sized {
-    fn parse_with_id<T: Read>(id: i32, reader: &mut T) -> Result<Self>;
-    fn parse<T: Read>(reader: &mut T) -> Result<Self> {
-        let id = VarInt::decode(reader).context("Failed to decode packet id")?.into();
-        Self::parse_with_id(id, reader)
+    // ids are resolved for `ProtocolVersion::current()`.
+    // decodes from the front of `buf`, so byte fields share the received buffer.
+    fn parse_with_id(id: i32, buf: &mut Bytes) -> Result<Self>;
+    fn packet_ids(version: ProtocolVersion) -> Vec<i32>;
+    fn parse(packet: &mut ReceivedPacket) -> Result<Self> {
+        let buf = &mut packet.buf;
+        let id = VarInt::decode_bytes(buf)
+            .context("Failed to decode packet id")?
+            .into();
+        let parsed = Self::parse_with_id(id, buf)?;
+        let warning = packet
+            .finish_with_strictness()
+            .with_context(|| format!("Packet 0x{:02x} is not fully consumed", id))?;
+        if let Some(warning) = warning {
+            packet.set_warning(format!(
+                "Packet 0x{:02x} is not fully consumed: {}",
+                id, warning
+            ));
+        }
+
+        Ok(parsed)
     }
 }
 
@@ -53,21 +79,23 @@ macro_rules! define_server_bound_packets {
         }
 
         impl PacketCluster for $enum_ident {
-            fn parse_with_id<T: std::io::Read>(id: i32, #[allow(unused)] reader: &mut T) -> Result<Self> {
-                #[deny(unreachable_patterns)]
-                match id {
-                    $(
-                        $struct_ident::PACKET_ID => Ok(Self::$struct_ident($struct_ident::decode(reader).with_context(|| format!("Failed to decode {}", stringify!($struct_ident)))?)),
-                    )*
-                    id => {
-                        bail!("Unknown packet id: {}", id)
+            fn parse_with_id(id: i32, #[allow(unused)] buf: &mut Bytes) -> Result<Self> {
+                let version = ProtocolVersion::current();
+                $(
+                    if $struct_ident::packet_id(version) == Some(id) {
+                        return Ok(Self::$struct_ident($struct_ident::decode_bytes(buf).with_context(|| format!("Failed to decode {}", stringify!($struct_ident)))?));
                     }
-                }
+                )*
+                bail!("Unknown packet id: {} (protocol version {})", id, version.0)
+            }
+            fn packet_ids(version: ProtocolVersion) -> Vec<i32> {
+                [$($struct_ident::packet_id(version),)*].into_iter().flatten().collect()
             }
         }
         impl $enum_ident {
             paste::paste! {
                 $(
+                    #[allow(irrefutable_let_patterns)] // clusters with a single packet
                     pub fn [<assume_ $snake_name>](self) -> Result<$struct_ident> {
                         if let Self::$struct_ident(inner) = self {
                             Ok(inner)
@@ -84,50 +112,38 @@ macro_rules! define_server_bound_packets {
     };
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+#[derive(Decodable, Debug, PartialEq, Eq, Clone, Copy, Hash)]
+#[repr_kind(varint)]
 pub enum HandshakeNextState {
+    #[value = 1]
     Status,
+    #[value = 2]
     Login,
 }
 
-impl Decodable for HandshakeNextState {
-    fn decode<T: Read>(reader: &mut T) -> Result<Self> {
-        match VarInt::decode(reader).context("Failed to decode next state")?.into() {
-            1 => Ok(Self::Status),
-            2 => Ok(Self::Login),
-            unknown => bail!("Unknown next state: {}", unknown),
-        }
-    }
-}
-
 define_server_bound_packets! {
     #[derive(Debug)]
     pub enum Handshaking {
-        #[sb_packet(0)]
+        #[sb_packet(State::Handshaking, "minecraft:intention")]
         #[derive(Decodable, Debug)]
         handshake: pub struct Handshake {
             pub protocol_version: VarInt,
+            #[cuber(max_len = 255)]
             pub server_address: String,
             pub server_port: u16,
             pub next_state: HandshakeNextState,
         }
-
-        #[sb_packet(0xfe)]
-        #[derive(Decodable, Debug)]
-        legacy_server_list_ping: pub struct LegacyServerListPing {
-            pub payload: u8,
-        }
     }
 }
 
 define_server_bound_packets! {
     #[derive(Debug)]
     pub enum Status {
-        #[sb_packet(0)]
+        #[sb_packet(State::Status, "minecraft:status_request")]
         #[derive(Decodable, Debug)]
         status_request: pub struct StatusRequest { }
 
-        #[sb_packet(1)]
+        #[sb_packet(State::Status, "minecraft:ping_request")]
         #[derive(Decodable, Debug)]
         ping_request: pub struct PingRequest {
             pub payload: i64,
@@ -138,41 +154,131 @@ define_server_bound_packets! {
 define_server_bound_packets! {
     #[derive(Debug)]
     pub enum Login {
-        #[sb_packet(0)]
-        #[derive(Decodable, Debug, PartialEq, Eq, Clone, Hash)]
+        #[sb_packet(State::Login, "minecraft:hello")]
+        #[derive(Debug, PartialEq, Eq, Clone, Hash)]
         login_start: pub struct LoginStart {
             pub name: String,
             pub uuid: BoolConditional<Uuid>,
         }
 
-        #[sb_packet(1)]
+        #[sb_packet(State::Login, "minecraft:key")]
         #[derive(Decodable, Debug, PartialEq, Eq, Clone, Hash)]
         encryption_response: pub struct EncryptionResponse {
-            pub shared_secret: Array<VarIntLength, u8>,
-            pub verify_token: Array<VarIntLength, u8>,
+            pub shared_secret: ByteArray<VarIntLength>,
+            pub verify_token: ByteArray<VarIntLength>,
         }
 
-        #[sb_packet(2)]
+        #[sb_packet(State::Login, "minecraft:custom_query_answer")]
         #[derive(Decodable, Debug, PartialEq, Eq, Clone, Hash)]
         plugin_response: pub struct PluginResponse {
             pub message_id: VarInt,
-            pub data: BoolConditional<Array<PacketInferredInBytes, u8>>,
+            pub data: BoolConditional<Bytes>,
+        }
+
+        #[sb_packet(State::Login, "minecraft:login_acknowledged", since = V1_20_2)]
+        #[derive(Decodable, Debug, PartialEq, Eq, Clone, Hash)]
+        login_acknowledged: pub struct LoginAcknowledged { }
+    }
+}
+
+// the uuid is no longer optional since 1.20.2.
+impl Decodable for LoginStart {
+    fn decode<T: Read>(reader: &mut T) -> Result<Self> {
+        let name = String::decode_bounded(reader, 16).context("Failed to decode name")?;
+        let uuid = if ProtocolVersion::current() >= ProtocolVersion::V1_20_2 {
+            Some(Uuid::decode(reader).context("Failed to decode uuid")?).into()
+        } else {
+            BoolConditional::decode(reader).context("Failed to decode uuid")?
+        };
+
+        Ok(Self { name, uuid })
+    }
+}
+
+impl ConnectionState for Handshaking {
+    const STATE: State = State::Handshaking;
+}
+impl ConnectionState for Status {
+    const STATE: State = State::Status;
+}
+impl ConnectionState for Login {
+    const STATE: State = State::Login;
+}
+
+define_server_bound_packets! {
+    #[derive(Debug)]
+    pub enum Configuration {
+        #[sb_packet(State::Configuration, "minecraft:client_information", since = V1_20_2)]
+        #[derive(Decodable, Debug, PartialEq, Eq, Clone, Hash)]
+        client_information: pub struct ConfigClientInformation {
+            #[cuber(max_len = 16)]
+            pub locale: String,
+            pub view_distance: i8,
+            pub chat_mode: VarInt,
+            pub chat_colors: bool,
+            pub displayed_skin_parts: u8,
+            pub main_hand: VarInt,
+            pub enable_text_filtering: bool,
+            pub allow_server_listings: bool,
+        }
+
+        #[sb_packet(State::Configuration, "minecraft:custom_payload", since = V1_20_2)]
+        #[derive(Decodable, Debug, PartialEq, Eq, Clone, Hash)]
+        plugin_message: pub struct ConfigPluginMessage {
+            pub channel: Identifier,
+            pub data: Bytes,
+        }
+
+        #[sb_packet(State::Configuration, "minecraft:finish_configuration", since = V1_20_2)]
+        #[derive(Decodable, Debug, PartialEq, Eq, Clone, Hash)]
+        finish_configuration: pub struct AcknowledgeFinishConfiguration { }
+
+        #[sb_packet(State::Configuration, "minecraft:keep_alive", since = V1_20_2)]
+        #[derive(Decodable, Debug, PartialEq, Eq, Clone, Hash)]
+        keep_alive: pub struct ConfigKeepAlive {
+            pub keep_alive_id: i64,
+        }
+
+        #[sb_packet(State::Configuration, "minecraft:pong", since = V1_20_2)]
+        #[derive(Decodable, Debug, PartialEq, Eq, Clone, Hash)]
+        pong: pub struct ConfigPong {
+            pub id: i32,
         }
     }
 }
 
+impl ConnectionState for Configuration {
+    const STATE: State = State::Configuration;
+}
+
+// same as `UUID.nameUUIDFromBytes(("OfflinePlayer:" + name).getBytes(UTF_8))` in vanilla.
+pub fn offline_uuid(name: &str) -> Uuid {
+    let digest = Md5::new()
+        .chain_update("OfflinePlayer:")
+        .chain_update(name)
+        .finalize();
+    uuid::Builder::from_md5_bytes(digest.into()).into_uuid()
+}
+
+impl LoginStart {
+    pub fn offline_uuid(&self) -> Uuid {
+        offline_uuid(&self.name)
+    }
+}
+
 define_server_bound_packets! {
     #[derive(Debug)]
     pub enum Play {
-        #[sb_packet(0x00)]
+        #[sb_packet(State::Play, "minecraft:accept_teleportation")]
         #[derive(Decodable, Debug, PartialEq, Eq, Clone, Hash)]
         confirm_teleportation: pub struct ConfirmTeleportation {
             pub teleport_id: VarInt,
         }
 
-        #[sb_packet(0x08)]
+        #[sb_packet(State::Play, "minecraft:client_information")]
         #[derive(Decodable, Debug, PartialEq, Eq, Clone, Hash)]
         client_information: pub struct ClientInformation {
+            #[cuber(max_len = 16)]
             pub locale: String,
             pub view_distance: i8,
             pub chat_mode: VarInt,
@@ -183,14 +289,14 @@ define_server_bound_packets! {
             pub allow_server_listings: bool,
         }
 
-        #[sb_packet(0x0d)]
+        #[sb_packet(State::Play, "minecraft:custom_payload")]
         #[derive(Decodable, Debug, PartialEq, Eq, Clone, Hash)]
         plugin_message: pub struct PlguinMessage {
             channel: Identifier,
-            data: Array<PacketInferredInBytes, u8>,
+            data: Bytes,
         }
 
-        #[sb_packet(0x14)]
+        #[sb_packet(State::Play, "minecraft:move_player_pos")]
         #[derive(Decodable, Debug, PartialEq, Clone)]
         set_player_position: pub struct SetPlayerPosition {
             pub x: f64,
@@ -199,7 +305,7 @@ define_server_bound_packets! {
             pub on_ground: bool,
         }
 
-        #[sb_packet(0x15)]
+        #[sb_packet(State::Play, "minecraft:move_player_pos_rot")]
         #[derive(Decodable, Debug, PartialEq, Clone)]
         set_player_position_and_rotation: pub struct SetPlayerPositionAndRotation {
             pub x: f64,
@@ -211,3 +317,152 @@ define_server_bound_packets! {
         }
     }
 }
+
+impl ConnectionState for Play {
+    const STATE: State = State::Play;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::common::Strictness;
+
+    #[test]
+    fn offline_uuid_of_name() {
+        assert_eq!(
+            offline_uuid("Notch"),
+            Uuid::parse_str("b50ad385-829d-3141-a216-7e7d7539ba7f").unwrap()
+        );
+        assert_eq!(
+            offline_uuid("jeb_"),
+            Uuid::parse_str("a762f560-4fce-3236-812a-b80efff0b62b").unwrap()
+        );
+    }
+
+    #[test]
+    fn login_start_offline_uuid() {
+        let ls = LoginStart {
+            name: "Notch".into(),
+            uuid: None.into(),
+        };
+        assert_eq!(ls.offline_uuid(), offline_uuid("Notch"));
+        assert_eq!(ls.offline_uuid().get_version_num(), 3);
+    }
+
+    fn ping_request_with_trailing_byte(strictness: Strictness) -> ReceivedPacket {
+        let mut buf = vec![0x01];
+        buf.extend_from_slice(&42_i64.to_be_bytes());
+        buf.push(0xff);
+        ReceivedPacket::new(buf.into_boxed_slice()).with_strictness(strictness)
+    }
+
+    #[test]
+    fn login_start_name_too_long() {
+        let mut buf = vec![0x00, 17];
+        buf.extend_from_slice(&[b'a'; 17]);
+        buf.push(0);
+        Login::parse(&mut ReceivedPacket::new(buf.into_boxed_slice())).unwrap_err();
+
+        let mut buf = vec![0x00, 16];
+        buf.extend_from_slice(&[b'a'; 16]);
+        buf.push(0);
+        let ls = Login::parse(&mut ReceivedPacket::new(buf.into_boxed_slice()))
+            .unwrap()
+            .unwrap_login_start();
+        assert_eq!(ls.name, "a".repeat(16));
+    }
+
+    #[test]
+    fn login_start_since_1_20_2() {
+        let uuid = offline_uuid("Notch");
+        let mut buf = vec![0x00, 5];
+        buf.extend_from_slice(b"Notch");
+        buf.extend_from_slice(uuid.as_bytes());
+
+        let ls = ProtocolVersion::V1_20_2.scope(|| {
+            Login::parse(&mut ReceivedPacket::new(buf.into_boxed_slice()))
+                .unwrap()
+                .unwrap_login_start()
+        });
+        assert_eq!(ls.uuid, Some(uuid).into());
+    }
+
+    #[test]
+    fn packet_ids_are_unique() {
+        fn assert_unique<C: PacketCluster>(version: ProtocolVersion) {
+            let mut ids = C::packet_ids(version);
+            let len = ids.len();
+            ids.sort();
+            ids.dedup();
+            assert_eq!(
+                ids.len(),
+                len,
+                "{} in {:?}",
+                std::any::type_name::<C>(),
+                version
+            );
+        }
+
+        for version in ProtocolVersion::SUPPORTED {
+            assert_unique::<Handshaking>(*version);
+            assert_unique::<Status>(*version);
+            assert_unique::<Login>(*version);
+            assert_unique::<Configuration>(*version);
+            assert_unique::<Play>(*version);
+        }
+        assert!(Configuration::packet_ids(ProtocolVersion::V1_20_1).is_empty());
+    }
+
+    #[test]
+    fn parse_by_version() {
+        let packet = || ReceivedPacket::new(vec![0x03].into_boxed_slice());
+
+        Login::parse(&mut packet()).unwrap_err();
+        let acknowledged = ProtocolVersion::V1_20_2.scope(|| Login::parse(&mut packet()).unwrap());
+        acknowledged.assume_login_acknowledged().unwrap();
+
+        let mut position = vec![0x16];
+        position.extend_from_slice(&[0; 25]);
+        let parsed = ProtocolVersion::V1_20_2
+            .scope(|| Play::parse(&mut ReceivedPacket::new(position.into_boxed_slice())).unwrap());
+        parsed.assume_set_player_position().unwrap();
+    }
+
+    #[test]
+    fn parse_strict() {
+        let mut packet = ping_request_with_trailing_byte(Strictness::Strict);
+        let e = Status::parse(&mut packet).unwrap_err();
+        assert!(format!("{:#}", e).contains("1 byte(s)"));
+    }
+
+    #[test]
+    fn parse_warn_and_ignore() {
+        for strictness in [Strictness::Warn, Strictness::Ignore] {
+            let mut packet = ping_request_with_trailing_byte(strictness);
+            let ping = Status::parse(&mut packet).unwrap().unwrap_ping_request();
+            assert_eq!(ping.payload, 42);
+            assert_eq!(packet.remaining(), 1);
+            assert_eq!(
+                packet.take_warning().is_some(),
+                strictness == Strictness::Warn
+            );
+        }
+    }
+
+    #[test]
+    fn parse_without_copying_bytes() {
+        let mut raw = vec![0x0d, 15];
+        raw.extend_from_slice(b"minecraft:brand");
+        raw.extend_from_slice(b"vanilla");
+        let raw = Bytes::from(raw);
+
+        let mut packet = ReceivedPacket::new(raw.clone());
+        let message = Play::parse(&mut packet).unwrap().unwrap_plugin_message();
+        assert_eq!(message.channel, "minecraft:brand".into());
+        assert_eq!(message.data, b"vanilla"[..]);
+
+        // the payload points into the received buffer.
+        let range = raw.as_ptr_range();
+        assert!(range.contains(&message.data.as_ptr()));
+    }
+}
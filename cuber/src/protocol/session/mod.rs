@@ -0,0 +1,370 @@
+use std::{collections::HashMap, future::Future};
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use uuid::Uuid;
+
+use super::{
+    client_bound::{Disconnect, LoginSuccess, LoginSuccessProperty},
+    encryption::ServerKey,
+    primitive::Chat,
+    server_bound::{Login, LoginStart},
+    Client,
+};
+
+use anyhow::{bail, Context as _, Result};
+
+pub const MOJANG_HAS_JOINED_URL: &str =
+    "https://sessionserver.mojang.com/session/minecraft/hasJoined";
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Hash)]
+pub struct GameProfile {
+    pub id: Uuid,
+    pub name: String,
+    #[serde(default)]
+    pub properties: Vec<ProfileProperty>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Hash)]
+pub struct ProfileProperty {
+    pub name: String,
+    pub value: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+impl From<ProfileProperty> for LoginSuccessProperty {
+    fn from(value: ProfileProperty) -> Self {
+        Self {
+            name: value.name,
+            value: value.value,
+            signature: value.signature.into(),
+        }
+    }
+}
+
+impl From<GameProfile> for LoginSuccess {
+    fn from(value: GameProfile) -> Self {
+        Self {
+            uuid: value.id,
+            user_name: value.name,
+            property: value
+                .properties
+                .into_iter()
+                .map(Into::into)
+                .collect::<Vec<_>>()
+                .into(),
+        }
+    }
+}
+
+// SHA-1 digest printed as a signed big-endian integer, like Java's `BigInteger::toString(16)`.
+pub fn server_hash(server_id: &str, shared_secret: &[u8], public_key_der: &[u8]) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(server_id.as_bytes());
+    hasher.update(shared_secret);
+    hasher.update(public_key_der);
+    let mut digest: [u8; 20] = hasher.finalize().into();
+
+    let negative = digest[0] & 0x80 != 0;
+    if negative {
+        let mut carry = true;
+        for byte in digest.iter_mut().rev() {
+            *byte = !*byte;
+            if carry {
+                (*byte, carry) = byte.overflowing_add(1);
+            }
+        }
+    }
+
+    let hex = digest
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+    let hex = match hex.trim_start_matches('0') {
+        "" => "0",
+        trimmed => trimmed,
+    };
+
+    if negative {
+        format!("-{}", hex)
+    } else {
+        hex.to_string()
+    }
+}
+
+pub trait SessionVerifier {
+    // `Ok(None)` means the player has not joined through the session service.
+    fn has_joined(
+        &self,
+        user_name: &str,
+        server_hash: &str,
+    ) -> impl Future<Output = Result<Option<GameProfile>>> + Send;
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpSessionVerifier {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl HttpSessionVerifier {
+    pub fn new() -> Self {
+        Self::with_url(MOJANG_HAS_JOINED_URL)
+    }
+    pub fn with_url(url: impl Into<String>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url: url.into(),
+        }
+    }
+}
+
+impl Default for HttpSessionVerifier {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SessionVerifier for HttpSessionVerifier {
+    async fn has_joined(&self, user_name: &str, server_hash: &str) -> Result<Option<GameProfile>> {
+        let response = self
+            .client
+            .get(&self.url)
+            .query(&[("username", user_name), ("serverId", server_hash)])
+            .send()
+            .await
+            .context("Failed to request session server")?;
+
+        match response.status() {
+            reqwest::StatusCode::OK => {
+                let body = response
+                    .bytes()
+                    .await
+                    .context("Failed to read session server response")?;
+                Ok(Some(
+                    serde_json::from_slice(&body).context("Failed to parse game profile")?,
+                ))
+            }
+            reqwest::StatusCode::NO_CONTENT => Ok(None),
+            status => bail!("Unexpected session server status: {}", status),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct MockSessionVerifier {
+    profiles: HashMap<String, GameProfile>,
+}
+
+impl MockSessionVerifier {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn insert(&mut self, profile: GameProfile) -> &mut Self {
+        self.profiles.insert(profile.name.clone(), profile);
+        self
+    }
+}
+
+impl SessionVerifier for MockSessionVerifier {
+    async fn has_joined(&self, user_name: &str, _server_hash: &str) -> Result<Option<GameProfile>> {
+        Ok(self.profiles.get(user_name).cloned())
+    }
+}
+
+// receives `LoginStart`, enables encryption and asks the session service who the player is.
+// LoginSuccess is left to the caller so that compression can be enabled before it.
+pub async fn login_online<V, R, W>(
+    client: &mut Client<Login, R, W>,
+    key: &ServerKey,
+    verifier: &V,
+) -> Result<GameProfile>
+where
+    V: SessionVerifier,
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let LoginStart { name, .. } = client.receive_packet().await?.assume_login_start()?;
+
+    let shared_secret = client.enable_encryption(key).await?;
+    let hash = server_hash("", &shared_secret, key.public_key_der());
+
+    match verifier.has_joined(&name, &hash).await? {
+        Some(profile) => Ok(profile),
+        None => {
+            client
+                .send_packet(Disconnect {
+                    chat: Chat::text("Failed to verify username!"),
+                })
+                .await?;
+            bail!("Failed to verify username: {}", name)
+        }
+    }
+}
+
+// offline servers trust the name and derive the uuid from it, ignoring the one sent by the client.
+pub async fn login_offline<R, W>(client: &mut Client<Login, R, W>) -> Result<GameProfile>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let login_start = client.receive_packet().await?.assume_login_start()?;
+
+    Ok(GameProfile {
+        id: login_start.offline_uuid(),
+        name: login_start.name,
+        properties: vec![],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash_of(name: &str) -> String {
+        server_hash(name, &[], &[])
+    }
+
+    #[test]
+    fn server_hash_positive() {
+        assert_eq!(hash_of("Notch"), "4ed1f46bbe04bc756bcb17c0c7ce3e4632f06a48");
+        assert_eq!(hash_of("simon"), "88e16a1019277b15d58faf0541e11910eb756f6");
+    }
+
+    #[test]
+    fn server_hash_negative() {
+        assert_eq!(hash_of("jeb_"), "-7c9d5b0044c130109a5d7b5fb5c317c02b4e28c1");
+    }
+
+    #[test]
+    fn game_profile_json() {
+        let json = r#"{
+            "id": "069a79f444e94726a5befca90e38aaf5",
+            "name": "Notch",
+            "properties": [
+                { "name": "textures", "value": "e30=", "signature": "c2lnbg==" }
+            ]
+        }"#;
+        let profile: GameProfile = serde_json::from_str(json).unwrap();
+
+        assert_eq!(
+            profile.id,
+            Uuid::parse_str("069a79f4-44e9-4726-a5be-fca90e38aaf5").unwrap()
+        );
+
+        let success = LoginSuccess::from(profile);
+        assert_eq!(success.user_name, "Notch");
+        assert_eq!(
+            success.property.inner,
+            vec![LoginSuccessProperty {
+                name: "textures".into(),
+                value: "e30=".into(),
+                signature: Some("c2lnbg==".to_string()).into(),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn login_online_with_mock() {
+        use crate::protocol::{
+            encryption::{CipherReader, CipherWriter},
+            primitive::{
+                array::{Array, VarIntLength},
+                VarInt,
+            },
+            receive_packet_plain_no_compression, send_packet_plain_no_compression, BuiltPacket,
+            Decodable, Encodable,
+        };
+        use rsa::{pkcs8::DecodePublicKey, Pkcs1v15Encrypt, RsaPublicKey};
+        use tokio::io::{AsyncWriteExt, BufReader};
+
+        fn build(id: i32, encode: impl FnOnce(&mut Vec<u8>)) -> BuiltPacket {
+            let mut buf = Vec::new();
+            VarInt(id).encode(&mut buf).unwrap();
+            encode(&mut buf);
+            BuiltPacket {
+                buf: buf.into_boxed_slice(),
+            }
+        }
+
+        let profile = GameProfile {
+            id: Uuid::parse_str("069a79f4-44e9-4726-a5be-fca90e38aaf5").unwrap(),
+            name: "Notch".into(),
+            properties: vec![ProfileProperty {
+                name: "textures".into(),
+                value: "e30=".into(),
+                signature: None,
+            }],
+        };
+        let mut verifier = MockSessionVerifier::new();
+        verifier.insert(profile.clone());
+
+        let (server_side, player_side) = tokio::io::duplex(4096);
+
+        let player = tokio::spawn(async move {
+            let (reader, writer) = tokio::io::split(player_side);
+            let mut reader = CipherReader::new(BufReader::new(reader));
+            let mut writer = CipherWriter::new(writer);
+
+            let login_start = build(0, |buf| {
+                "Notch".to_string().encode(buf).unwrap();
+                false.encode(buf).unwrap();
+            });
+            send_packet_plain_no_compression(&mut writer, login_start)
+                .await
+                .unwrap();
+
+            let mut request = receive_packet_plain_no_compression(&mut reader)
+                .await
+                .unwrap();
+            assert_eq!(VarInt::decode(&mut request).unwrap(), VarInt(1));
+            assert_eq!(u8::decode(&mut request).unwrap(), 0); // empty server id
+            let public_key = Array::<VarIntLength, u8>::decode(&mut request).unwrap();
+            let verify_token = Array::<VarIntLength, u8>::decode(&mut request).unwrap();
+
+            let public_key = RsaPublicKey::from_public_key_der(&public_key.inner).unwrap();
+            let secret = [7; 16];
+            let encrypt = |data: &[u8]| -> Array<VarIntLength, u8> {
+                public_key
+                    .encrypt(&mut rand::thread_rng(), Pkcs1v15Encrypt, data)
+                    .unwrap()
+                    .into()
+            };
+            let response = build(1, |buf| {
+                encrypt(&secret).encode(buf).unwrap();
+                encrypt(&verify_token.inner).encode(buf).unwrap();
+            });
+            send_packet_plain_no_compression(&mut writer, response)
+                .await
+                .unwrap();
+            writer.flush().await.unwrap();
+
+            reader.enable(&secret);
+            writer.enable(&secret);
+
+            let mut success = receive_packet_plain_no_compression(&mut reader)
+                .await
+                .unwrap();
+            assert_eq!(VarInt::decode(&mut success).unwrap(), VarInt(2));
+            let _uuid = u128::decode(&mut success).unwrap();
+            assert_eq!(String::decode(&mut success).unwrap(), "Notch");
+            let mut rest = Vec::new();
+            std::io::Read::read_to_end(&mut success, &mut rest).unwrap();
+        });
+
+        let mut client = Client::from_io(server_side).into_login();
+
+        let key = ServerKey::generate().unwrap();
+        let joined = login_online(&mut client, &key, &verifier).await.unwrap();
+        assert_eq!(joined, profile);
+
+        client
+            .finish_login(LoginSuccess::from(joined))
+            .await
+            .unwrap();
+        player.await.unwrap();
+    }
+}
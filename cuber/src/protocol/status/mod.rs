@@ -0,0 +1,200 @@
+use std::io::{Read, Write};
+
+use base64::Engine as _;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncWrite};
+use uuid::Uuid;
+
+use super::{
+    client_bound::{PingResponse, StatusResponse},
+    primitive::Chat,
+    server_bound::{PingRequest, Status},
+    Client, Decodable, Encodable,
+};
+
+use anyhow::{Context as _, Result};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerStatus {
+    pub version: StatusVersion,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub players: Option<StatusPlayers>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<Chat>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub favicon: Option<String>,
+    #[serde(default)]
+    pub enforces_secure_chat: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct StatusVersion {
+    pub name: String,
+    pub protocol: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct StatusPlayers {
+    pub max: i32,
+    pub online: i32,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sample: Vec<StatusPlayerSample>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct StatusPlayerSample {
+    pub name: String,
+    pub id: Uuid,
+}
+
+impl ServerStatus {
+    // the client only accepts 64x64 PNG images.
+    pub fn set_favicon_png(&mut self, png: &[u8]) -> &mut Self {
+        self.favicon = Some(format!(
+            "data:image/png;base64,{}",
+            base64::engine::general_purpose::STANDARD.encode(png)
+        ));
+        self
+    }
+}
+
+impl Encodable for ServerStatus {
+    fn encode<T: Write>(&self, writer: &mut T) -> Result<usize> {
+        serde_json::to_string(self)
+            .context("Failed to serialize server status")?
+            .encode(writer)
+    }
+    fn encoded_len(&self) -> usize {
+        serde_json::to_string(self)
+            .expect("server status always serializes")
+            .encoded_len()
+    }
+}
+
+impl Decodable for ServerStatus {
+    fn decode<T: Read>(reader: &mut T) -> Result<Self> {
+        serde_json::from_str(&String::decode(reader)?).context("Failed to parse server status")
+    }
+}
+
+// answers the status request and the ping that follows it, then the connection is done.
+pub async fn handle_status<R, W>(
+    client: &mut Client<Status, R, W>,
+    status: &ServerStatus,
+) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    loop {
+        match client.receive_packet().await? {
+            Status::StatusRequest(_) => {
+                client
+                    .send_packet(StatusResponse {
+                        json_response: status.clone(),
+                    })
+                    .await?;
+            }
+            Status::PingRequest(PingRequest { payload }) => {
+                client.send_packet(PingResponse { payload }).await?;
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{
+        primitive::VarInt, receive_packet_plain_no_compression, send_packet_plain_no_compression,
+        BuiltPacket,
+    };
+
+    fn status() -> ServerStatus {
+        ServerStatus {
+            version: StatusVersion {
+                name: "1.20.1".into(),
+                protocol: 763,
+            },
+            players: Some(StatusPlayers {
+                max: 20,
+                online: 1,
+                sample: vec![StatusPlayerSample {
+                    name: "Notch".into(),
+                    id: Uuid::parse_str("069a79f4-44e9-4726-a5be-fca90e38aaf5").unwrap(),
+                }],
+            }),
+            description: Some(Chat::text("Hello")),
+            favicon: None,
+            enforces_secure_chat: true,
+        }
+    }
+
+    #[test]
+    fn status_json() {
+        let json: serde_json::Value = serde_json::to_value(status()).unwrap();
+
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "version": { "name": "1.20.1", "protocol": 763 },
+                "players": {
+                    "max": 20,
+                    "online": 1,
+                    "sample": [{ "name": "Notch", "id": "069a79f4-44e9-4726-a5be-fca90e38aaf5" }],
+                },
+                "description": { "text": "Hello" },
+                "enforcesSecureChat": true,
+            })
+        );
+        assert_eq!(
+            serde_json::from_value::<ServerStatus>(json).unwrap(),
+            status()
+        );
+    }
+
+    #[test]
+    fn favicon() {
+        let mut status = status();
+        status.set_favicon_png(&[0x89, b'P', b'N', b'G']);
+
+        assert_eq!(status.favicon.unwrap(), "data:image/png;base64,iVBORw==");
+    }
+
+    #[tokio::test]
+    async fn status_and_ping() {
+        let (server_side, mut player_side) = tokio::io::duplex(4096);
+        let mut server = Client::from_io(server_side).into_status();
+
+        let handler = tokio::spawn(async move { handle_status(&mut server, &status()).await });
+
+        let mut ping = vec![0x01];
+        ping.extend_from_slice(&0x1234_i64.to_be_bytes());
+        for packet in [vec![0x00], ping.clone()] {
+            send_packet_plain_no_compression(
+                &mut player_side,
+                BuiltPacket {
+                    buf: packet.into_boxed_slice(),
+                },
+            )
+            .await
+            .unwrap();
+        }
+
+        let mut response = receive_packet_plain_no_compression(&mut player_side)
+            .await
+            .unwrap();
+        assert_eq!(VarInt::decode(&mut response).unwrap(), VarInt(0));
+        assert_eq!(ServerStatus::decode(&mut response).unwrap(), status());
+
+        let mut pong = receive_packet_plain_no_compression(&mut player_side)
+            .await
+            .unwrap();
+        assert_eq!(VarInt::decode(&mut pong).unwrap(), VarInt(1));
+        assert_eq!(i64::decode(&mut pong).unwrap(), 0x1234);
+
+        handler.await.unwrap().unwrap();
+    }
+}
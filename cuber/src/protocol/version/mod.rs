@@ -0,0 +1,92 @@
+use std::cell::Cell;
+
+// the protocol version of the connection being encoded or decoded.
+// `Encodable` and `Decodable` have no room for it, so it is kept per thread while a packet is built or parsed.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
+pub struct ProtocolVersion(pub i32);
+
+impl ProtocolVersion {
+    pub const V1_19_4: Self = Self(762);
+    pub const V1_20_1: Self = Self(763);
+    pub const V1_20_2: Self = Self(764);
+    // not in `SUPPORTED`: there is no 1.20.3 report, so it only selects the NBT chat format in tests.
+    pub const V1_20_3: Self = Self(765);
+
+    pub const DEFAULT: Self = Self::V1_20_1;
+    // versions that have a packet id table, in ascending order.
+    pub const SUPPORTED: &'static [Self] = &[Self::V1_19_4, Self::V1_20_1, Self::V1_20_2];
+
+    pub fn is_supported(self) -> bool {
+        Self::SUPPORTED.contains(&self)
+    }
+    pub fn name(self) -> Option<&'static str> {
+        match self {
+            Self::V1_19_4 => Some("1.19.4"),
+            Self::V1_20_1 => Some("1.20.1"),
+            Self::V1_20_2 => Some("1.20.2"),
+            Self::V1_20_3 => Some("1.20.3"),
+            _ => None,
+        }
+    }
+
+    pub fn current() -> Self {
+        CURRENT.with(Cell::get)
+    }
+    pub fn scope<T>(self, f: impl FnOnce() -> T) -> T {
+        struct Restore(ProtocolVersion);
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                CURRENT.with(|current| current.set(self.0));
+            }
+        }
+
+        let _restore = Restore(CURRENT.with(|current| current.replace(self)));
+        f()
+    }
+}
+
+impl Default for ProtocolVersion {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl From<i32> for ProtocolVersion {
+    fn from(value: i32) -> Self {
+        Self(value)
+    }
+}
+
+thread_local! {
+    static CURRENT: Cell<ProtocolVersion> = const { Cell::new(ProtocolVersion::DEFAULT) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn supported_versions() {
+        assert!(ProtocolVersion::SUPPORTED.windows(2).all(|w| w[0] < w[1]));
+        assert!(ProtocolVersion::DEFAULT.is_supported());
+        assert!(!ProtocolVersion(47).is_supported());
+        assert_eq!(ProtocolVersion::V1_20_2.name(), Some("1.20.2"));
+    }
+
+    #[test]
+    fn scope_is_restored() {
+        assert_eq!(ProtocolVersion::current(), ProtocolVersion::DEFAULT);
+
+        ProtocolVersion::V1_20_3.scope(|| {
+            assert_eq!(ProtocolVersion::current(), ProtocolVersion::V1_20_3);
+            ProtocolVersion::V1_19_4.scope(|| {
+                assert_eq!(ProtocolVersion::current(), ProtocolVersion::V1_19_4);
+            });
+            assert_eq!(ProtocolVersion::current(), ProtocolVersion::V1_20_3);
+        });
+
+        let result = std::panic::catch_unwind(|| ProtocolVersion::V1_20_2.scope(|| panic!()));
+        assert!(result.is_err());
+        assert_eq!(ProtocolVersion::current(), ProtocolVersion::DEFAULT);
+    }
+}